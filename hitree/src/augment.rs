@@ -0,0 +1,78 @@
+/// Aggregate kept for every subtree of a tree, computed from the values in the subtree in order.
+///
+/// The aggregate of a subtree is `combine(combine(left, leaf(value)), right)`, so `combine` must be associative.
+/// It does not need to be commutative, and there is no need for an identity element, empty subtrees have no aggregate.
+/// Sums, minimums, maximums and their combinations in a tuple all qualify.
+///
+/// The aggregates are maintained on every change of the tree structure at the cost of O(log n) calls to `combine`.
+/// The unit type `()` is the default augment, keeping no aggregate at all.
+///
+/// # Examples:
+/// ```
+///     use hitree::augment::Augment;
+///     use hitree::hiset::HiSet;
+///
+///     /// Sum of volumes of price levels
+///     struct Volume;
+///
+///     impl Augment<(u32,u64)> for Volume {
+///         type Value = u64;
+///         fn leaf(value: &(u32,u64)) -> u64 { value.1 }
+///         fn combine(left: u64, right: u64) -> u64 { left + right }
+///     }
+///
+///     let levels: HiSet<(u32,u64), Volume> = [(101,5),(100,10),(102,1)].into_iter().collect();
+///     assert_eq!(levels.aggregate_by_index(..2), Some(15));
+/// ```
+pub trait Augment<T> {
+    /// Type of the aggregate
+    type Value: Clone;
+
+    /// Aggregate of a single value
+    fn leaf(value: &T) -> Self::Value;
+
+    /// Aggregate of two adjacent ranges of values, `left` preceding `right`
+    fn combine(left: Self::Value, right: Self::Value) -> Self::Value;
+}
+
+/// No aggregate
+impl <T> Augment<T> for () {
+    type Value = ();
+
+    #[inline]
+    fn leaf(_value: &T) {}
+
+    #[inline]
+    fn combine(_left: (), _right: ()) {}
+}
+
+
+/// Value with a weight, for use with the [Weight] augment.
+/// Weights must not be negative.
+pub trait Weighted {
+    /// Type of the weight, usually an integer or a float
+    type Weight: Copy + Default + PartialOrd + std::ops::Add<Output=Self::Weight> + std::ops::Sub<Output=Self::Weight>;
+
+    /// Weight of this value
+    fn weight(&self) -> Self::Weight;
+}
+
+/// Augment keeping sum of weights of all values in every subtree.
+/// Enables weighted rank search with [index_at_weight](`crate::hiset::HiSet<T,Weight>::index_at_weight`).
+pub struct Weight;
+
+impl <T> Augment<T> for Weight
+    where T: Weighted
+{
+    type Value = T::Weight;
+
+    #[inline]
+    fn leaf(value: &T) -> T::Weight {
+        value.weight()
+    }
+
+    #[inline]
+    fn combine(left: T::Weight, right: T::Weight) -> T::Weight {
+        left + right
+    }
+}
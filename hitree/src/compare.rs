@@ -0,0 +1,49 @@
+use std::cmp::Ordering;
+
+/// Ordering of values in a tree, used in place of their `Ord` implementation.
+///
+/// Any closure or function taking two references and returning `Ordering` is a comparator,
+/// so the ordering can be chosen at runtime. The comparator must define a total order, just like `Ord`.
+/// Operations on two sets, like [append](`crate::hiset::HiSet<T>::append`) or [union](`crate::hiset::HiSet<T>::union`),
+/// expect both of them to be ordered the same way, by equally configured comparators.
+///
+/// A comparator for `T` also enables lookup by key type `KEY` where the set holds `T: Borrow<KEY>`,
+/// if it implements `Comparator<KEY>` as well. [OrdComparator] does so for all types implementing `Ord`.
+///
+/// # Examples:
+/// ```
+///     use hitree::hiset::HiSet;
+///
+///     let mut s = HiSet::with_comparator(|a: &String, b: &String| a.to_lowercase().cmp(&b.to_lowercase()) );
+///     s.insert("banana");
+///     s.insert("Apple");
+///     s.insert("apple");      // equal to "Apple", not inserted
+///     assert!(s.iter().eq(["Apple","banana"].iter()));
+///     assert!(s.contains(&"APPLE".to_string()));
+/// ```
+pub trait Comparator<T: ?Sized> {
+    /// Compare two values, like `Ord::cmp`
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Default comparator, ordering values by their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrdComparator;
+
+impl <T> Comparator<T> for OrdComparator
+    where T: ?Sized + Ord
+{
+    #[inline]
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        Ord::cmp(a, b)
+    }
+}
+
+impl <T,F> Comparator<T> for F
+    where T: ?Sized, F: Fn(&T, &T) -> Ordering
+{
+    #[inline]
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
@@ -0,0 +1,685 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug,Formatter};
+use std::hash::{Hash,Hasher};
+use std::ops::Bound::Included;
+use super::augment::Augment;
+use super::compare::{Comparator, OrdComparator};
+use super::hiset::{HiSet, HiSetIterator, HiSetOwnedIterator, Quantile};
+
+/// Ordered multiset of values, accessible by value or index of value in the bag.
+/// Unlike [HiSet], equal values are all kept, each in its own node, so indexing counts every duplicate.
+/// A value is inserted after the values equal to it that are already in the bag.
+///
+/// The tree is the same as the one of [HiSet], and so are the iterators, quantiles and aggregates.
+/// Values are ordered by their `Ord` implementation, unless a [Comparator] `C` is given with [with_comparator](`HiBag<T,(),C>::with_comparator`).
+pub struct HiBag<T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    set: HiSet<T,A,C>,
+}
+
+impl <T> HiBag<T>
+    where T: Ord
+{
+    /// Create new empty `HiBag`.
+    ///
+    /// Does not allocate anything.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # #[allow(unused_mut)]
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::new();
+    /// ```
+    pub fn new() -> HiBag<T> {
+        HiBag { set: HiSet::new() }
+    }
+
+    /// Create a `HiBag` from a vector of values in any order, keeping all duplicates.
+    /// Sorting takes O(n log n), building the tree O(n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<i32>::from_vec(vec![5,3,1,3,4,2,5]);
+    ///     assert!(bag.iter().eq([1,2,3,3,4,5,5].iter()));
+    /// ```
+    pub fn from_vec(values: Vec<T>) -> HiBag<T> {
+        HiBag { set: HiSet::from_vec_with_duplicates(values, OrdComparator) }
+    }
+}
+
+
+impl <T,C> HiBag<T,(),C>
+    where C: Comparator<T>
+{
+    /// Create new empty `HiBag` ordering its values by given comparator instead of their `Ord` implementation.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::with_comparator(|a: &String, b: &String| a.len().cmp(&b.len()) );
+    ///     bag.insert("pear");
+    ///     bag.insert("fig");
+    ///     bag.insert("kiwi");
+    ///     assert!(bag.iter().eq(["fig","pear","kiwi"].iter()));
+    /// ```
+    pub fn with_comparator(comparator: C) -> HiBag<T,(),C> {
+        HiBag { set: HiSet::with_comparator(comparator) }
+    }
+}
+
+
+impl <T,A,C> HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Create new empty `HiBag` keeping aggregates given by augment `A` and ordering its values by given comparator.
+    /// Use [with_comparator](`HiBag<T,(),C>::with_comparator`) for bags without an augment.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     use hitree::augment::Augment;
+    ///
+    ///     struct Sum;
+    ///
+    ///     impl Augment<u32> for Sum {
+    ///         type Value = u32;
+    ///         fn leaf(value: &u32) -> u32 { *value }
+    ///         fn combine(left: u32, right: u32) -> u32 { left + right }
+    ///     }
+    ///
+    ///     let mut bag = HiBag::<u32,Sum,_>::with_augment_and_comparator(|a: &u32, b: &u32| b.cmp(a) );
+    ///     bag.extend([1,5,3,5]);
+    ///     assert!(bag.iter().eq([5,5,3,1].iter()));
+    ///     assert_eq!(bag.aggregate_by_index(..3), Some(13));
+    /// ```
+    pub fn with_augment_and_comparator(comparator: C) -> HiBag<T,A,C> {
+        HiBag { set: HiSet::with_augment_and_comparator(comparator) }
+    }
+
+    /// Borrow the comparator ordering values of this bag.
+    pub fn comparator(&self) -> &C {
+        self.set.comparator()
+    }
+
+    /// Return current number of values in the bag, counting every duplicate.
+    ///
+    /// Extremely cheap.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<i32>::from_vec(vec![1,1,2]);
+    ///     assert_eq!(bag.len(), 3);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    /// Return true if the bag contains no values.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<i32>::new();
+    ///     assert!(bag.is_empty());
+    ///     bag.insert(1);
+    ///     assert!(!bag.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// Insert a value into the bag. Always succeeds, the value is placed after all values equal to it. Takes O(log n).
+    /// Value can be any type that can be converted into the value type using `Into` trait.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut latencies = HiBag::<u32>::new();
+    ///     for ms in [12_u32, 7, 12, 30, 12] {
+    ///         latencies.insert(ms);
+    ///     }
+    ///     assert_eq!(latencies.len(), 5);
+    ///     assert!(latencies.iter().eq([7,12,12,12,30].iter()));
+    /// ```
+    pub fn insert(&mut self, value: impl Into<T>) {
+        self.set.insert_duplicate(value.into());
+    }
+
+    /// Return true if at least one value equal to the key is in the bag.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<String>::from(["a","b","b"].into_iter());
+    ///     assert!(bag.contains("b"));
+    ///     assert!(!bag.contains("c"));
+    /// ```
+    pub fn contains<KEY>(&self, key: &KEY) -> bool
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.set.contains(key)
+    }
+
+    /// Return number of values equal to the key. Takes O(log n) regardless of the number of duplicates.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![3,1,3,2,3]);
+    ///     assert_eq!(bag.count_of(&3), 3);
+    ///     assert_eq!(bag.count_of(&1), 1);
+    ///     assert_eq!(bag.count_of(&4), 0);
+    /// ```
+    pub fn count_of<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.equal_range_index(key).len()
+    }
+
+    /// Return index range of the values equal to the key.
+    /// If there are none, the range is empty and starts at the index the key would have if it was inserted.
+    /// Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![10,20,20,20,30]);
+    ///     assert_eq!(bag.equal_range_index(&20), 1..4);
+    ///     assert_eq!(bag.equal_range_index(&25), 4..4);
+    /// ```
+    pub fn equal_range_index<KEY>(&self, key: &KEY) -> std::ops::Range<usize>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.set.lower_bound_index(key)..self.set.upper_bound_index(key)
+    }
+
+    /// Return index of the first value not less than the key.
+    /// Returns `len()` if all values are less than the key.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<i32>::from_vec(vec![10,20,20,30]);
+    ///     assert_eq!(bag.lower_bound_index(&20), 1);
+    ///     assert_eq!(bag.lower_bound_index(&25), 3);
+    /// ```
+    pub fn lower_bound_index<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.set.lower_bound_index(key)
+    }
+
+    /// Return index of the first value greater than the key.
+    /// Returns `len()` if no value is greater than the key.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<i32>::from_vec(vec![10,20,20,30]);
+    ///     assert_eq!(bag.upper_bound_index(&20), 3);
+    ///     assert_eq!(bag.upper_bound_index(&30), 4);
+    /// ```
+    pub fn upper_bound_index<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.set.upper_bound_index(key)
+    }
+
+    /// Get a shared borrow of value from the bag by index. Duplicates have consecutive indexes.
+    /// Borrowed value can be any shared reference type that can be borrowed from T.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<String>::from(["b","a","b"].into_iter());
+    ///     assert_eq!(bag.get_by_index::<str>(0), Some("a"));
+    ///     assert_eq!(bag.get_by_index::<str>(1), Some("b"));
+    ///     assert_eq!(bag.get_by_index::<str>(2), Some("b"));
+    ///     assert_eq!(bag.get_by_index::<str>(3), None);
+    /// ```
+    pub fn get_by_index<B>(&self, index: usize) -> Option<&B>
+        where T: Borrow<B>,
+              B: ?Sized
+    {
+        self.set.get_by_index(index)
+    }
+
+    /// Remove one value equal to the key and return it, or `None` if there is no such value.
+    /// Of several equal values, the first one is removed. Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::from_vec(vec![1,2,2,3]);
+    ///     assert_eq!(bag.remove_one(&2), Some(2));
+    ///     assert!(bag.iter().eq([1,2,3].iter()));
+    ///     assert_eq!(bag.remove_one(&4), None);
+    /// ```
+    pub fn remove_one<KEY>(&mut self, key: &KEY) -> Option<T>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let std::ops::Range { start, end } = self.equal_range_index(key);
+        if start < end {
+            self.set.take_by_index(start)
+        } else {
+            None
+        }
+    }
+
+    /// Remove all values equal to the key and return how many were removed.
+    /// The values are cut out of the tree with a few splits and joins, so it takes O(log n) plus dropping them.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::from_vec(vec![1,2,2,2,3]);
+    ///     assert_eq!(bag.remove_all(&2), 3);
+    ///     assert!(bag.iter().eq([1,3].iter()));
+    ///     assert_eq!(bag.remove_all(&2), 0);
+    /// ```
+    pub fn remove_all<KEY>(&mut self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.set.drain_range::<KEY,_>((Included(key), Included(key))).len()
+    }
+
+    /// Remove value by index and return it, or `None` if the index is out of bounds. Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::from_vec(vec![1,2,2,3]);
+    ///     assert_eq!(bag.take_by_index(2), Some(2));
+    ///     assert!(bag.iter().eq([1,2,3].iter()));
+    /// ```
+    pub fn take_by_index(&mut self, index: usize) -> Option<T> {
+        self.set.take_by_index(index)
+    }
+
+    /// Remove the first value and return it, or `None` if the bag is empty.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::from_vec(vec![2,1,1]);
+    ///     assert_eq!(bag.take_first(), Some(1));
+    ///     assert_eq!(bag.take_first(), Some(1));
+    ///     assert_eq!(bag.take_first(), Some(2));
+    ///     assert_eq!(bag.take_first(), None);
+    /// ```
+    pub fn take_first(&mut self) -> Option<T> {
+        self.set.take_first()
+    }
+
+    /// Remove the last value and return it, or `None` if the bag is empty.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let mut bag = HiBag::<u32>::from_vec(vec![2,1,2]);
+    ///     assert_eq!(bag.take_last(), Some(2));
+    ///     assert_eq!(bag.len(), 2);
+    /// ```
+    pub fn take_last(&mut self) -> Option<T> {
+        self.set.take_last()
+    }
+
+    /// Get double ended iterator over `&T` in ascending order, duplicates included.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<char>::from_vec(vec!['b','a','b']);
+    ///     assert_eq!(bag.iter().rev().collect::<String>(), "bba");
+    /// ```
+    pub fn iter(&self) -> HiSetIterator<'_,T,A> {
+        self.set.iter()
+    }
+
+    /// Return double ended iterator over `&T` in given index range.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![1,1,2,2,3]);
+    ///     assert!(bag.range_by_index(1..4).eq([1,2,2].iter()));
+    /// ```
+    pub fn range_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> HiSetIterator<'_,T,A> {
+        self.set.range_by_index(range)
+    }
+
+    /// Return index range of values falling within given key bounds, all duplicates of the bounds included.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![1,2,2,3,3,4]);
+    ///     assert_eq!(bag.index_range(2..4), 1..5);
+    ///     assert_eq!(bag.index_range(2..=2), 1..3);
+    /// ```
+    pub fn index_range<KEY,R>(&self, range: R) -> std::ops::Range<usize>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        self.set.index_range(range)
+    }
+
+    /// Return double ended iterator over `&T` with values falling within given key bounds.
+    /// Bounds don't need to be present in the bag.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![1,2,2,3,3,4]);
+    ///     assert!(bag.range(2..4).eq([2,2,3,3].iter()));
+    /// ```
+    pub fn range<KEY,R>(&self, range: R) -> HiSetIterator<'_,T,A>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        self.set.range(range)
+    }
+
+    /// Return number of values falling within given key bounds, duplicates included. Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let scores = HiBag::<u32>::from_vec(vec![50,70,70,70,90,100]);
+    ///     assert_eq!(scores.count_range(70..=90), 4);
+    /// ```
+    pub fn count_range<KEY,R>(&self, range: R) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        self.set.count_range(range)
+    }
+
+    /// Return the `q`-quantile of the values, duplicates included, for `q` between 0.0 and 1.0.
+    /// See [HiSet::quantile] for the details. Returns `None` if the bag is empty. Takes O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between 0.0 and 1.0.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let latencies = HiBag::<u32>::from_vec(vec![5,5,5,5,5,5,5,5,5,80]);
+    ///     assert_eq!(latencies.quantile(0.5).unwrap().nearest(), (4, &5));
+    ///     assert_eq!(latencies.quantile(1.0).unwrap().nearest(), (9, &80));
+    /// ```
+    pub fn quantile(&self, q: f64) -> Option<Quantile<'_,T>> {
+        self.set.quantile(q)
+    }
+
+    /// Return the `p`-th percentile, for `p` between 0.0 and 100.0. Same as `quantile(p / 100.0)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 0.0 and 100.0.
+    pub fn percentile(&self, p: f64) -> Option<Quantile<'_,T>> {
+        self.set.percentile(p)
+    }
+
+    /// Return the median, same as `quantile(0.5)`.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![1,2,2,9]);
+    ///     assert_eq!(bag.median().unwrap().midpoint(), 2.0);
+    /// ```
+    pub fn median(&self) -> Option<Quantile<'_,T>> {
+        self.set.median()
+    }
+
+    /// Return aggregate of values in given index range, see [HiSet::aggregate_by_index]. Takes O(log n).
+    pub fn aggregate_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> Option<A::Value> {
+        self.set.aggregate_by_index(range)
+    }
+
+    /// Return aggregate of values falling within given key bounds, see [HiSet::aggregate]. Takes O(log n).
+    pub fn aggregate<KEY,R>(&self, range: R) -> Option<A::Value>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        self.set.aggregate(range)
+    }
+}
+
+
+impl <T,A,C> Clone for HiBag<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    fn clone(&self) -> Self {
+        HiBag { set: self.set.clone() }
+    }
+}
+
+impl <T,A,C> Default for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default
+{
+    /// Create new empty `HiBag`, same as [new](`HiBag<T>::new`).
+    fn default() -> Self {
+        HiBag { set: HiSet::default() }
+    }
+}
+
+impl <T,A,C,I,X,O> From<I> for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default,
+          I: Iterator<Item=X>,
+          O: Into<T>,
+          X: ToOwned<Owned=O>
+{
+    /// Construct `HiBag<T>` from an `Iterator` of values that can be made into owned instances of `T`.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hibag::HiBag;
+    /// let bag = HiBag::<String>::from( ["to","be","or","not","to","be"].into_iter() );
+    /// assert!(bag.iter().eq(["be","be","not","or","to","to"].iter()));
+    /// ```
+    fn from(iterator: I) -> Self {
+        iterator.map(|value| value.to_owned().into()).collect()
+    }
+}
+
+impl <T,A,C> std::ops::Index<usize> for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Output = T;
+
+    /// Borrow value by index, same as [get_by_index](`HiBag<T>::get_by_index`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    fn index(&self, index: usize) -> &T {
+        &self.set[index]
+    }
+}
+
+impl <T,A,C> FromIterator<T> for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default
+{
+    /// Collect values into a `HiBag`, keeping all duplicates in the order they came in.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hibag::HiBag;
+    /// let bag: HiBag<i32> = [3,1,2,1].into_iter().collect();
+    /// assert!(bag.iter().eq([1,1,2,3].iter()));
+    /// ```
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+        HiBag { set: HiSet::from_vec_with_duplicates(iter.into_iter().collect(), C::default()) }
+    }
+}
+
+impl <T,A,C> Extend<T> for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Insert all values from the iterator.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hibag::HiBag;
+    /// let mut bag = HiBag::<i32>::from_vec(vec![1,2]);
+    /// bag.extend([2,3]);
+    /// assert!(bag.iter().eq([1,2,2,3].iter()));
+    /// ```
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl <'a,T,A,C> Extend<&'a T> for HiBag<T,A,C>
+    where T: 'a + Copy, A: Augment<T>, C: Comparator<T>
+{
+    fn extend<I: IntoIterator<Item=&'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl <T,A,C> Debug for HiBag<T,A,C>
+    where T: Debug, A: Augment<T>, C: Comparator<T>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.set.fmt(f)
+    }
+}
+
+impl <T,A,C> PartialEq for HiBag<T,A,C>
+    where T: PartialEq, A: Augment<T>, C: Comparator<T>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.set == other.set
+    }
+}
+
+impl <T,A,C> Eq for HiBag<T,A,C>
+    where T: Eq, A: Augment<T>, C: Comparator<T>
+{}
+
+impl <T,A,C> PartialOrd for HiBag<T,A,C>
+    where T: PartialOrd, A: Augment<T>, C: Comparator<T>
+{
+    /// Compare bags lexicographically by their values in iteration order, ignoring the comparators like `eq`.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.set.partial_cmp(&other.set)
+    }
+}
+
+impl <T,A,C> Ord for HiBag<T,A,C>
+    where T: Ord, A: Augment<T>, C: Comparator<T>
+{
+    /// Compare bags lexicographically by their values in iteration order, ignoring the comparators like `eq`.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.set.cmp(&other.set)
+    }
+}
+
+impl <T,A,C> Hash for HiBag<T,A,C>
+    where T: Hash, A: Augment<T>, C: Comparator<T>
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.set.hash(state);
+    }
+}
+
+impl <T,A,C> IntoIterator for HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = T;
+    type IntoIter = HiSetOwnedIterator<T,A>;
+
+    /// Consume the bag and return double ended iterator over its values in order.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hibag::HiBag;
+    ///     let bag = HiBag::<u32>::from_vec(vec![2,1,2]);
+    ///     assert_eq!(bag.into_iter().collect::<Vec<_>>(), vec![1,2,2]);
+    /// ```
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.into_iter()
+    }
+}
+
+impl <'bag,T,A,C> IntoIterator for &'bag HiBag<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'bag T;
+    type IntoIter = HiSetIterator<'bag,T,A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+#[test]
+fn test_hibag() {
+        let mut bag = HiBag::<u32>::new();
+        let mut model = Vec::new();
+        for i in 0..300_u32 {
+            let value = (i * 37) % 41;
+            bag.insert(value);
+            let position = model.partition_point(|&v| v <= value);
+            model.insert(position, value);
+        }
+        assert!(bag.iter().eq(model.iter()));
+        for (index, value) in model.iter().enumerate() {
+            assert_eq!(bag.get_by_index(index), Some(value));
+        }
+        for key in 0..42 {
+            let start = model.partition_point(|&v| v < key);
+            let end = model.partition_point(|&v| v <= key);
+            assert_eq!(bag.equal_range_index(&key), start..end);
+            assert_eq!(bag.count_of(&key), end - start);
+            assert_eq!(bag.contains(&key), start < end);
+        }
+        for key in (0..42).step_by(3) {
+            assert_eq!(bag.remove_one(&key), if model.contains(&key) { Some(key) } else { None });
+            if let Some(position) = model.iter().position(|&v| v == key) {
+                model.remove(position);
+            }
+        }
+        assert!(bag.iter().eq(model.iter()));
+        for key in (1..42).step_by(4) {
+            let removed = bag.remove_all(&key);
+            assert_eq!(removed, model.iter().filter(|&&v| v == key).count());
+            model.retain(|&v| v != key);
+            assert_eq!(bag.count_of(&key), 0);
+        }
+        assert!(bag.iter().eq(model.iter()));
+        assert_eq!(bag.len(), model.len());
+        assert_eq!(bag.count_range(10..20), model.iter().filter(|&&v| (10..20).contains(&v)).count());
+        let collected: HiBag<u32> = model.iter().rev().copied().collect();
+        assert_eq!(collected, bag);
+}
+
+#[test]
+fn test_hibag_stable_duplicates() {
+        // values equal by the comparator keep their insertion order
+        let by_first = |a: &(u32,char), b: &(u32,char)| a.0.cmp(&b.0);
+        let mut bag = HiBag::with_comparator(by_first);
+        for (index, letter) in "abcdefghij".chars().enumerate() {
+            bag.insert(((index % 3) as u32, letter));
+        }
+        let letters: String = bag.iter().map(|value| value.1 ).collect();
+        assert_eq!(letters, "adgjbehcfi");
+        assert_eq!(bag.remove_one(&(1, ' ')), Some((1, 'b')));
+        assert_eq!(bag.equal_range_index(&(1, ' ')), 4..6);
+}
@@ -1,14 +1,23 @@
-#![allow(dead_code)]
-
-
-
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+use std::fmt::{Debug,Formatter};
+use std::hash::{Hash,Hasher};
+use super::{tree_height, index_bounds};
 
+/// Ordered map of keys to values, accessible by key or index of key in the map.
+/// Stores entries in a balanced binary tree with subtree node count tracking.
+/// Nodes are allocated on the heap using `Box`.
+#[derive(Clone)]
 pub struct HiMap<K,V>
     where K: Ord
 {
     root: Ref<K,V>,
 }
 
+/// Reference to a subtree of `Node`s, including node count of subtree pointed to by it.
+#[derive(Clone)]
 struct Ref<K,V>
     where K: Ord
 {
@@ -16,6 +25,9 @@ struct Ref<K,V>
     node: Option<Box<Node<K,V>>>,
 }
 
+/// Node holding a key, its value and references to the left (lesser) and right (greater) subtrees.
+/// Balancing rules are the same as for `HiSet` nodes.
+#[derive(Clone)]
 struct Node<K,V>
     where K: Ord
 {
@@ -24,3 +36,2343 @@ struct Node<K,V>
     left: Ref<K,V>,
     right: Ref<K,V>,
 }
+
+
+
+impl <K,V> HiMap<K,V>
+    where K: Ord
+{
+    /// Create new empty `HiMap`.
+    ///
+    /// Does not allocate anything.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # #[allow(unused_mut)]
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,i32>::new();
+    /// ```
+    pub fn new() -> HiMap<K,V> {
+        HiMap { root: Ref::default() }
+    }
+
+
+    /// Return current number of entries in the map.
+    ///
+    /// Extremely cheap.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let map = HiMap::<i32,i32>::new();
+    ///     assert_eq!(map.len(), 0);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.root.count
+    }
+
+    /// Return true if the map contains no entries.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     assert!(map.is_empty());
+    ///     map.insert(1, 10);
+    ///     assert!(!map.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+
+    /// Insert a key-value pair into the map.
+    /// If the key was not in the map, return `None`.
+    /// If the key was already in the map, replace the value and return the old one.
+    /// The key is not updated in that case.
+    /// Key can be any type that can be converted into `K` using `Into` trait.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,i32>::new();
+    ///     assert_eq!(map.insert("one", 1), None);
+    ///     assert_eq!(map.insert("two", 2), None);
+    ///     assert_eq!(map.insert("one", 10), Some(1));
+    ///     assert_eq!(map.len(), 2);
+    ///     assert_eq!(map.get("one"), Some(&10));
+    /// ```
+    pub fn insert(&mut self, key: impl Into<K>, value: V) -> Option<V> {
+        self.root.insert(Node::new(key, value))
+    }
+
+
+    /// Get shared borrows of key and value from the map by index.
+    /// Entries in the map are sorted according to `Ord` trait of the key,
+    /// index 0 is the entry with the smallest key.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("This", 0);
+    ///     map.insert("is", 1);
+    ///     map.insert("a", 2);
+    ///     map.insert("test!", 3);
+    ///
+    ///     assert_eq!(map.get_by_index(0), Some((&"This".to_string(), &0)));
+    ///     assert_eq!(map.get_by_index(1), Some((&"a".to_string(), &2)));
+    ///     assert_eq!(map.get_by_index(2), Some((&"is".to_string(), &1)));
+    ///     assert_eq!(map.get_by_index(3), Some((&"test!".to_string(), &3)));
+    ///     assert_eq!(map.get_by_index(4), None);
+    /// ```
+    pub fn get_by_index(&self, index: usize) -> Option<(&K,&V)> {
+        let mut index_to_find = index;
+        let mut current_node = self.root.node();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match node.left.count.cmp(&index_to_find) {
+                        Ordering::Greater => {
+                            // index must be in the left subtree
+                            current_node = node.left.node();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some((&node.key, &node.value))
+                        },
+                        Ordering::Less => {
+                            // index must be in the right subtree
+                            index_to_find = index_to_find - 1 - node.left.count;
+                            current_node = node.right.node();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get shared borrow of key and mutable borrow of value from the map by index.
+    /// Entries in the map are sorted according to `Ord` trait of the key,
+    /// index 0 is the entry with the smallest key.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("first", 0);
+    ///     map.insert("second", 0);
+    ///     map.insert("third", 0);
+    ///
+    ///     if let Some((_key, value)) = map.get_by_index_mut(1) {
+    ///         *value += 1;
+    ///     }
+    ///
+    ///     assert_eq!(map.get("first"), Some(&0));
+    ///     assert_eq!(map.get("second"), Some(&1));
+    ///     assert_eq!(map.get("third"), Some(&0));
+    /// ```
+    pub fn get_by_index_mut(&mut self, index: usize) -> Option<(&K,&mut V)> {
+        self.root.node_by_index_mut(index).map(|node| (&node.key, &mut node.value) )
+    }
+
+
+    /// Borrow a value from the map by a key reference.
+    /// Reference type of key must have the same `Ord` ordering as `&K`.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("This", 4);
+    ///     map.insert("is", 2);
+    ///     map.insert("a", 1);
+    ///     map.insert("test!", 5);
+    ///
+    ///     assert_eq!(map.get("test!"), Some(&5));
+    ///     assert_eq!(map.get("not there"), None);
+    ///     assert_eq!(map.get(&"This".to_string()), Some(&4));
+    /// ```
+    pub fn get<KEY>(&self, key: &KEY) -> Option<&V>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find(key).map(|node| &node.value )
+    }
+
+    /// Borrow key and value from the map by a key reference.
+    /// Reference type of key must have the same `Ord` ordering as `&K`.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("key", 3);
+    ///
+    ///     assert_eq!(map.get_key_value("key"), Some((&"key".to_string(), &3)));
+    ///     assert_eq!(map.get_key_value("not there"), None);
+    /// ```
+    pub fn get_key_value<KEY>(&self, key: &KEY) -> Option<(&K,&V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find(key).map(|node| (&node.key, &node.value) )
+    }
+
+    /// Borrow mutably a value from the map by a key reference.
+    /// Reference type of key must have the same `Ord` ordering as `&K`.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("counter", 0);
+    ///
+    ///     if let Some(counter) = map.get_mut("counter") {
+    ///         *counter += 1;
+    ///     }
+    ///     assert_eq!(map.get("counter"), Some(&1));
+    ///     assert_eq!(map.get_mut("not there"), None);
+    /// ```
+    pub fn get_mut<KEY>(&mut self, key: &KEY) -> Option<&mut V>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        let mut current_node = self.root.node_mut();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match Ord::cmp(node.key.borrow(), key) {
+                        Ordering::Greater => {
+                            // key must be in the left subtree
+                            current_node = node.left.node_mut();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some(&mut node.value)
+                        },
+                        Ordering::Less => {
+                            // key must be in the right subtree
+                            current_node = node.right.node_mut();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return true if the map contains an entry for the key.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("key", 3);
+    ///
+    ///     assert!(map.contains_key("key"));
+    ///     assert!(!map.contains_key("not there"));
+    /// ```
+    pub fn contains_key<KEY>(&self, key: &KEY) -> bool
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find(key).is_some()
+    }
+
+
+    /// Find index of entry given by key reference.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,usize>::new();
+    ///     map.insert("This", 0);
+    ///     map.insert("is", 1);
+    ///     map.insert("a", 2);
+    ///     map.insert("test!", 3);
+    ///
+    ///     assert_eq!(map.index_of("This"), Some(0));
+    ///     assert_eq!(map.index_of("a"), Some(1));
+    ///     assert_eq!(map.index_of("is"), Some(2));
+    ///     assert_eq!(map.index_of("test!"), Some(3));
+    ///     assert_eq!(map.index_of("nonexistent"), None);
+    /// ```
+    pub fn index_of<KEY>(&self, key: &KEY) -> Option<usize>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find_index(key).ok()
+    }
+
+
+    /// Get the entry for a key for in-place manipulation.
+    /// The entry knows its index in the map, whether the key is present or not.
+    /// Finding the entry takes a single descent from the root.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut word_count = HiMap::<String,usize>::new();
+    ///     for word in ["a", "b", "a", "c", "a"] {
+    ///         *word_count.entry(word).or_insert(0) += 1;
+    ///     }
+    ///     assert_eq!(word_count.get("a"), Some(&3));
+    ///     assert_eq!(word_count.get("b"), Some(&1));
+    ///
+    ///     // the entry can tell where the key is or would be
+    ///     assert_eq!(word_count.entry("b").index(), 1);
+    ///     assert_eq!(word_count.entry("bb").index(), 2);
+    /// ```
+    pub fn entry(&mut self, key: impl Into<K>) -> Entry<'_,K,V> {
+        let key = key.into();
+        match self.root.find_index(&key) {
+            Ok(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
+            Err(index) => Entry::Vacant(VacantEntry { map: self, key, index }),
+        }
+    }
+
+
+    /// Find the entry with the largest key less than or equal to the key and return its index, key and value.
+    /// The key does not need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(20, "twenty");
+    ///     map.insert(30, "thirty");
+    ///
+    ///     assert_eq!(map.floor(&5), None);
+    ///     assert_eq!(map.floor(&20), Some((1, &20, &"twenty")));
+    ///     assert_eq!(map.floor(&25), Some((1, &20, &"twenty")));
+    ///     assert_eq!(map.floor(&35), Some((2, &30, &"thirty")));
+    /// ```
+    pub fn floor<KEY>(&self, key: &KEY) -> Option<(usize,&K,&V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find_closest(key, true, true).map(|(index, node)| (index, &node.key, &node.value) )
+    }
+
+    /// Find the entry with the smallest key greater than or equal to the key and return its index, key and value.
+    /// The key does not need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(20, "twenty");
+    ///     map.insert(30, "thirty");
+    ///
+    ///     assert_eq!(map.ceiling(&5), Some((0, &10, &"ten")));
+    ///     assert_eq!(map.ceiling(&20), Some((1, &20, &"twenty")));
+    ///     assert_eq!(map.ceiling(&25), Some((2, &30, &"thirty")));
+    ///     assert_eq!(map.ceiling(&35), None);
+    /// ```
+    pub fn ceiling<KEY>(&self, key: &KEY) -> Option<(usize,&K,&V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find_closest(key, false, true).map(|(index, node)| (index, &node.key, &node.value) )
+    }
+
+    /// Find the entry with the largest key strictly less than the key and return its index, key and value.
+    /// The key does not need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(20, "twenty");
+    ///     map.insert(30, "thirty");
+    ///
+    ///     assert_eq!(map.prev(&10), None);
+    ///     assert_eq!(map.prev(&20), Some((0, &10, &"ten")));
+    ///     assert_eq!(map.prev(&25), Some((1, &20, &"twenty")));
+    /// ```
+    pub fn prev<KEY>(&self, key: &KEY) -> Option<(usize,&K,&V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find_closest(key, true, false).map(|(index, node)| (index, &node.key, &node.value) )
+    }
+
+    /// Find the entry with the smallest key strictly greater than the key and return its index, key and value.
+    /// The key does not need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(20, "twenty");
+    ///     map.insert(30, "thirty");
+    ///
+    ///     assert_eq!(map.next(&5), Some((0, &10, &"ten")));
+    ///     assert_eq!(map.next(&20), Some((2, &30, &"thirty")));
+    ///     assert_eq!(map.next(&30), None);
+    /// ```
+    pub fn next<KEY>(&self, key: &KEY) -> Option<(usize,&K,&V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.find_closest(key, false, false).map(|(index, node)| (index, &node.key, &node.value) )
+    }
+
+
+
+    /// Remove the entry with the smallest key from the map and return it.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(15, "fifteen");
+    ///     map.insert(5, "five");
+    ///
+    ///     assert_eq!(map.take_first(), Some((5, "five")));
+    ///     assert_eq!(map.take_first(), Some((10, "ten")));
+    ///     assert_eq!(map.take_first(), Some((15, "fifteen")));
+    ///     assert_eq!(map.len(), 0);
+    ///     assert_eq!(map.take_first(), None);
+    /// ```
+    pub fn take_first(&mut self) -> Option<(K,V)> {
+        self.root.take_leftmost_node().map(|node| (node.key, node.value) )
+    }
+
+    /// Remove the entry with the largest key from the map and return it.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(10, "ten");
+    ///     map.insert(15, "fifteen");
+    ///     map.insert(5, "five");
+    ///
+    ///     assert_eq!(map.take_last(), Some((15, "fifteen")));
+    ///     assert_eq!(map.take_last(), Some((10, "ten")));
+    ///     assert_eq!(map.take_last(), Some((5, "five")));
+    ///     assert_eq!(map.len(), 0);
+    ///     assert_eq!(map.take_last(), None);
+    /// ```
+    pub fn take_last(&mut self) -> Option<(K,V)> {
+        self.root.take_rightmost_node().map(|node| (node.key, node.value) )
+    }
+
+    /// Remove an entry by index and return its key and value.
+    ///
+    ///  # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,i32>::new();
+    ///     map.insert("first", 1);
+    ///     map.insert("second", 2);
+    ///     map.insert("third", 3);
+    ///
+    ///     assert_eq!(map.take_by_index(2), Some(("third".to_string(), 3)));
+    ///     assert_eq!(map.len(), 2);
+    ///
+    ///     assert_eq!(map.take_by_index(3), None);
+    ///
+    ///     assert_eq!(map.take_by_index(0), Some(("first".to_string(), 1)));
+    ///     assert_eq!(map.take_by_index(0), Some(("second".to_string(), 2)));
+    ///     assert_eq!(map.len(), 0);
+    /// ```
+    pub fn take_by_index(&mut self, index: usize) -> Option<(K,V)> {
+        self.root.take_node_by_index(index).map(|node| (node.key, node.value) )
+    }
+
+
+    /// Remove an entry by key reference and return its value.
+    /// Whatever you use as key must give the same `Ord` results as `Ord` on `&K`!
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,i32>::new();
+    ///     map.insert("first", 1);
+    ///     map.insert("second", 2);
+    ///     map.insert("third", 3);
+    ///
+    ///     assert_eq!(map.remove("second"), Some(2));
+    ///     assert_eq!(map.len(), 2);
+    ///     assert_eq!(map.remove("second"), None);
+    ///
+    ///     assert_eq!(map.remove(&"third".to_string()), Some(3));
+    ///     assert_eq!(map.len(), 1);
+    /// ```
+    pub fn remove<KEY>(&mut self, key: &KEY) -> Option<V>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.take_node_by_key(key).map(|node| node.value )
+    }
+
+    /// Remove an entry by key reference and return both its key and value.
+    /// Whatever you use as key must give the same `Ord` results as `Ord` on `&K`!
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<String,i32>::new();
+    ///     map.insert("first", 1);
+    ///
+    ///     assert_eq!(map.remove_entry("first"), Some(("first".to_string(), 1)));
+    ///     assert_eq!(map.remove_entry("first"), None);
+    /// ```
+    pub fn remove_entry<KEY>(&mut self, key: &KEY) -> Option<(K,V)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        self.root.take_node_by_key(key).map(|node| (node.key, node.value) )
+    }
+
+
+    /// Return iterator over all `(&K,&V)` pairs in key order.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,&str>::new();
+    ///     map.insert(2, "two");
+    ///     map.insert(1, "one");
+    ///
+    ///     let mut i = map.iter();
+    ///     assert_eq!(i.next(), Some((&1, &"one")));
+    ///     assert_eq!(i.next(), Some((&2, &"two")));
+    ///     assert_eq!(i.next(), None);
+    /// ```
+    pub fn iter(&self) -> HiMapIterator<'_,K,V> {
+        HiMapIterator::new(&self.root, 0, self.root.count)
+    }
+
+    /// Return iterator over all `(&K,&mut V)` pairs in key order.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     map.insert(2, 20);
+    ///     map.insert(1, 10);
+    ///
+    ///     for (key, value) in map.iter_mut() {
+    ///         *value += *key;
+    ///     }
+    ///     assert!(map.values().eq([11, 22].iter()));
+    /// ```
+    pub fn iter_mut(&mut self) -> HiMapIteratorMut<'_,K,V> {
+        let end = self.root.count;
+        HiMapIteratorMut::new(&mut self.root, 0, end)
+    }
+
+    /// Return double ended iterator over `(&K,&V)` pairs in given index range.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     for i in 0..7 {
+    ///         map.insert(i, i * 10);
+    ///     }
+    ///     assert!(map.range_by_index(2..=4).eq([(&2,&20),(&3,&30),(&4,&40)].into_iter()));
+    ///     assert!(map.range_by_index(5..).keys().rev().eq([6,5].iter()));
+    /// ```
+    pub fn range_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> HiMapIterator<'_,K,V> {
+        let (start, end) = index_bounds(range, self.root.count);
+
+        HiMapIterator::new(&self.root, start, end)
+    }
+
+    /// Return double ended iterator over `(&K,&mut V)` pairs in given index range.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     for i in 0..7 {
+    ///         map.insert(i, 0);
+    ///     }
+    ///     for value in map.range_by_index_mut(..3).values() {
+    ///         *value = 1;
+    ///     }
+    ///     assert!(map.values().eq([1,1,1,0,0,0,0].iter()));
+    /// ```
+    pub fn range_by_index_mut(&mut self, range: impl std::ops::RangeBounds<usize>) -> HiMapIteratorMut<'_,K,V> {
+        let (start, end) = index_bounds(range, self.root.count);
+
+        HiMapIteratorMut::new(&mut self.root, start, end)
+    }
+
+
+    /// Return range of indexes of entries with keys falling within given key bounds.
+    /// Bounds don't need to be present in the map.
+    /// If the start bound is above the end bound, returned range is empty.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,()>::new();
+    ///     for key in [0,10,20,30,40,50] {
+    ///         map.insert(key, ());
+    ///     }
+    ///     assert_eq!(map.index_range(15..=40), 2..5);
+    ///     assert_eq!(map.index_range(20..40), 2..4);
+    ///     assert_eq!(map.index_range(60..), 6..6);
+    /// ```
+    pub fn index_range<KEY,R>(&self, range: R) -> std::ops::Range<usize>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        use std::ops::Bound::*;
+        let start = match range.start_bound() {
+            Included(key) => self.root.find_index(key).unwrap_or_else(|index| index),
+            Excluded(key) => self.root.find_index(key).map_or_else(|index| index, |index| index + 1),
+            Unbounded => 0
+        };
+        let end = match range.end_bound() {
+            Included(key) => self.root.find_index(key).map_or_else(|index| index, |index| index + 1),
+            Excluded(key) => self.root.find_index(key).unwrap_or_else(|index| index),
+            Unbounded => self.root.count
+        };
+        start..end.max(start)
+    }
+
+    /// Return double ended iterator over `(&K,&V)` pairs with keys falling within given key bounds.
+    /// Bounds don't need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     for key in [0,10,20,30,40,50] {
+    ///         map.insert(key, key / 10);
+    ///     }
+    ///     assert!(map.range(15..=40).values().eq([2,3,4].iter()));
+    ///     assert!(map.range(..15).keys().rev().eq([10,0].iter()));
+    /// ```
+    pub fn range<KEY,R>(&self, range: R) -> HiMapIterator<'_,K,V>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
+
+        HiMapIterator::new(&self.root, start, end)
+    }
+
+    /// Return double ended iterator over `(&K,&mut V)` pairs with keys falling within given key bounds.
+    /// Bounds don't need to be present in the map.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<i32,i32>::new();
+    ///     for key in [0,10,20,30,40,50] {
+    ///         map.insert(key, 0);
+    ///     }
+    ///     for value in map.range_mut(15..=40).values() {
+    ///         *value = 1;
+    ///     }
+    ///     assert!(map.values().eq([0,0,1,1,1,0].iter()));
+    /// ```
+    pub fn range_mut<KEY,R>(&mut self, range: R) -> HiMapIteratorMut<'_,K,V>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
+
+        HiMapIteratorMut::new(&mut self.root, start, end)
+    }
+
+    /// Return iterator over all keys in order.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.insert("b", 2);
+    ///     map.insert("a", 1);
+    ///     assert!(map.keys().eq(["a","b"].iter()));
+    /// ```
+    pub fn keys(&self) -> HiMapKeys<'_,K,V> {
+        self.iter().keys()
+    }
+
+    /// Return iterator over all values in order of their keys.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.insert("b", 2);
+    ///     map.insert("a", 1);
+    ///     assert!(map.values().eq([1,2].iter()));
+    /// ```
+    pub fn values(&self) -> HiMapValues<'_,K,V> {
+        self.iter().values()
+    }
+
+    /// Return iterator over mutable borrows of all values in order of their keys.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.insert("b", 2);
+    ///     map.insert("a", 1);
+    ///     map.values_mut().for_each(|value| *value *= 10);
+    ///     assert!(map.values().eq([10,20].iter()));
+    /// ```
+    pub fn values_mut(&mut self) -> HiMapValuesMut<'_,K,V> {
+        self.iter_mut().values()
+    }
+
+}
+
+
+impl <K,V> Default for HiMap<K,V>
+    where K: Ord
+{
+    /// Create new empty `HiMap`, same as [new](`HiMap<K,V>::new`).
+    fn default() -> Self {
+        HiMap::new()
+    }
+}
+
+impl <K,V> FromIterator<(K,V)> for HiMap<K,V>
+    where K: Ord
+{
+    /// Collect key-value pairs into a `HiMap`. Of entries with equal keys, the last value is kept,
+    /// same as if they were inserted one by one.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::himap::HiMap;
+    /// let m: HiMap<i32,&str> = [(2,"two"),(1,"one"),(2,"TWO")].into_iter().collect();
+    /// assert!(m.iter().eq([(&1,&"one"),(&2,&"TWO")].into_iter()));
+    /// ```
+    fn from_iter<I: IntoIterator<Item=(K,V)>>(iter: I) -> Self {
+        let mut entries: Vec<(K,V)> = iter.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0) );    // stable sort keeps insertion order of equal keys
+        let mut unique: Vec<(K,V)> = Vec::with_capacity(entries.len());
+        for (key, value) in entries {
+            match unique.last_mut() {
+                Some(last) if last.0 == key => last.1 = value,
+                _ => unique.push((key, value)),
+            }
+        }
+        let count = unique.len();
+        HiMap { root: Ref::from_sorted(&mut unique.into_iter(), count) }
+    }
+}
+
+impl <K,V> Extend<(K,V)> for HiMap<K,V>
+    where K: Ord
+{
+    /// Insert all key-value pairs from the iterator, replacing values of keys already in the map.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::himap::HiMap;
+    /// let mut m: HiMap<i32,i32> = [(1,1),(2,2)].into_iter().collect();
+    /// m.extend([(2,20),(3,30)]);
+    /// assert!(m.values().eq([1,20,30].iter()));
+    /// ```
+    fn extend<I: IntoIterator<Item=(K,V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl <'a,K,V> Extend<(&'a K,&'a V)> for HiMap<K,V>
+    where K: 'a + Ord + Copy,
+          V: 'a + Copy
+{
+    fn extend<I: IntoIterator<Item=(&'a K,&'a V)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(|(key, value)| (*key, *value) ));
+    }
+}
+
+impl <K,V> Debug for HiMap<K,V>
+    where K: Ord + Debug,
+          V: Debug
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl <K,V> PartialEq for HiMap<K,V>
+    where K: Ord,
+          V: PartialEq
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl <K,V> Eq for HiMap<K,V>
+    where K: Ord,
+          V: Eq
+{}
+
+impl <K,V> PartialOrd for HiMap<K,V>
+    where K: Ord,
+          V: PartialOrd
+{
+    /// Compare maps lexicographically by their `(key, value)` pairs in order.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl <K,V> Ord for HiMap<K,V>
+    where K: Ord,
+          V: Ord
+{
+    /// Compare maps lexicographically by their `(key, value)` pairs in order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl <K,V> Hash for HiMap<K,V>
+    where K: Ord + Hash,
+          V: Hash
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for entry in self.iter() {
+            entry.hash(state);
+        }
+    }
+}
+
+
+
+#[test]
+fn test_himap_insert_remove() {
+    let mut m = HiMap::<i32,i32>::new();
+    for i in 0..100 {
+        assert_eq!(m.insert((i * 37) % 100, i), None);
+    }
+    assert_eq!(m.len(), 100);
+    for i in 0..100 {
+        assert_eq!(m.get(&((i * 37) % 100)), Some(&i));
+    }
+    for i in (0..100).step_by(2) {
+        assert_eq!(m.remove(&((i * 37) % 100)), Some(i));
+    }
+    assert_eq!(m.len(), 50);
+    for i in 0..100 {
+        assert_eq!(m.contains_key(&((i * 37) % 100)), i % 2 == 1);
+    }
+}
+
+
+#[test]
+fn test_himap_by_index() {
+    let mut m = HiMap::<i32,i32>::new();
+    for i in 0..100 {
+        m.insert((i * 37) % 100, -((i * 37) % 100));
+    }
+    for i in 0..100 {
+        assert_eq!(m.get_by_index(i as usize), Some((&i, &-i)));
+        assert_eq!(m.index_of(&i), Some(i as usize));
+    }
+    assert_eq!(m.take_by_index(50), Some((50, -50)));
+    assert_eq!(m.index_of(&51), Some(50));
+    for i in (51..100).rev() {
+        assert_eq!(m.take_last(), Some((i, -i)));
+    }
+    for i in 0..50 {
+        assert_eq!(m.take_first(), Some((i, -i)));
+    }
+    assert!(m.is_empty());
+}
+
+
+#[test]
+fn test_himap_entry_insert() {
+    // inserting through an entry must build the same tree as `insert`
+    fn same_shape<K: Ord, V>(a: &Ref<K,V>, b: &Ref<K,V>) -> bool {
+        a.count == b.count && match (a.node(), b.node()) {
+            (Some(a), Some(b)) => a.key == b.key && same_shape(&a.left, &b.left) && same_shape(&a.right, &b.right),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+    for order in [1, 37, 99] {
+        let mut m = HiMap::<i32,i32>::new();
+        let mut n = HiMap::<i32,i32>::new();
+        for i in 0..100 {
+            let key = (i * order) % 100;
+            *m.entry(key).or_insert(0) += key;
+            assert_eq!(*m.entry(key).or_insert(-1), key);
+            n.insert(key, key);
+            assert!(same_shape(&m.root, &n.root));
+        }
+        assert!(m.iter().map(|(key, value)| (*key, *value)).eq((0..100).map(|i| (i, i))));
+    }
+}
+
+
+#[test]
+fn test_himap_iterators() {
+    let mut m = HiMap::<i32,i32>::new();
+    for i in 0..50 {
+        m.insert((i * 37) % 50, i);
+    }
+    assert!(m.keys().copied().eq(0..50));
+    assert!(m.range_by_index(10..20).keys().rev().copied().eq((10..20).rev()));
+    for (key, value) in m.range_by_index_mut(40..) {
+        *value = *key;
+    }
+    assert!(m.range_by_index(40..).values().copied().eq(40..50));
+    assert!(m.clone().into_iter().map(|(key, _)| key ).eq(0..50));
+    assert!(m.into_iter().rev().map(|(key, _)| key ).eq((0..50).rev()));
+}
+
+
+#[test]
+fn test_himap_std_traits() {
+    use std::collections::HashSet;
+    let a: HiMap<i32,i32> = (0..20).rev().map(|i| (i, -i)).collect();
+    assert!(a.keys().copied().eq(0..20));
+    assert!(a.values().copied().eq((0..20).map(|i| -i)));
+    let b = a.clone();
+    assert_eq!(a, b);
+    assert_eq!(format!("{:?}", [(1,"a")].into_iter().collect::<HiMap<i32,&str>>()), "{1: \"a\"}");
+    let mut c = b.clone();
+    c.extend([(&5, &5)]);
+    assert_ne!(a, c);
+    assert!(a < c);
+    let hashes: HashSet<HiMap<i32,i32>> = [a, b, c].into_iter().collect();
+    assert_eq!(hashes.len(), 2);
+}
+
+
+#[test]
+fn test_himap_iter_mut_mixed_ends() {
+    // Borrows returned from both ends must never alias: hold all of them at once, then touch each one.
+    let mut m: HiMap<i32,usize> = (0..15).map(|i| (i, 0)).collect();
+    for start in 0..15 {
+        let mut i = m.range_by_index_mut(start..);
+        let mut borrowed = Vec::new();
+        while let Some((_, value)) = if borrowed.len() % 2 == 0 { i.next_back() } else { i.next() } {
+            borrowed.push(value);
+        }
+        for value in borrowed {
+            *value += 1;
+        }
+    }
+    assert!(m.iter().all(|(key, value)| *value == *key as usize + 1));
+}
+
+
+#[test]
+fn test_himap_iterator_nth() {
+    let m: HiMap<i32,i32> = (0..30).map(|i| (i, i * 2)).collect();
+    let mut i = m.range_by_index(5..25);
+    assert_eq!(i.len(), 20);
+    assert_eq!(i.nth(3), Some((&8, &16)));
+    assert_eq!(i.nth_back(3), Some((&21, &42)));
+    assert_eq!(i.len(), 12);
+    assert!(m.values().skip(10).take(3).copied().eq([20, 22, 24]));
+    assert_eq!(m.keys().nth(30), None);
+    let (start, end) = (20, 10);
+    assert_eq!(m.range_by_index(start..end).len(), 0);
+}
+
+
+//---------------- Iterators -------------------------------------------------
+
+/// Iterator over `(&K,&V)` pairs of a `HiMap` in an index range.
+/// Returned by [iter](`HiMap<K,V>::iter`) and [range_by_index](`HiMap<K,V>::range_by_index`).
+pub struct HiMapIterator<'map,K,V>
+    where K: Ord
+{
+    pending: VecDeque<Pending<'map,K,V>>,
+    start:  usize,
+    end:    usize,
+}
+
+/// Part of the tree not visited yet by `HiMapIterator`, in order: either a single entry or a whole subtree.
+enum Pending<'map,K,V>
+    where K: Ord
+{
+    Entry(&'map K, &'map V),
+    Subtree(&'map Ref<K,V>),
+}
+
+impl <'map,K,V> HiMapIterator<'map,K,V>
+    where K: Ord
+{
+    /// Create iterator over entries with index in `start..end` within subtree pointed to by `root`.
+    fn new(root: &'map Ref<K,V>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.pending_range(start, end, &mut pending);
+        }
+        HiMapIterator { pending, start, end }
+    }
+
+    /// Turn into iterator over keys only.
+    pub fn keys(self) -> HiMapKeys<'map,K,V> {
+        HiMapKeys { inner: self }
+    }
+
+    /// Turn into iterator over values only.
+    pub fn values(self) -> HiMapValues<'map,K,V> {
+        HiMapValues { inner: self }
+    }
+}
+
+impl <'map,K,V> Iterator for HiMapIterator<'map,K,V>
+    where K: Ord
+{
+    type Item = (&'map K, &'map V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                Pending::Entry(key, value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                Pending::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree, continue with its left part
+                        let node = subtree.node().unwrap();    // only non-empty subtrees are pending
+                        if !node.right.is_empty() {
+                            self.pending.push_front(Pending::Subtree(&node.right));
+                        }
+                        self.pending.push_front(Pending::Entry(&node.key, &node.value));
+                        if !node.left.is_empty() {
+                            self.pending.push_front(Pending::Subtree(&node.left));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'map,K,V> DoubleEndedIterator for HiMapIterator<'map,K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                Pending::Entry(key, value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                Pending::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree, continue with its right part
+                        let node = subtree.node().unwrap();    // only non-empty subtrees are pending
+                        if !node.left.is_empty() {
+                            self.pending.push_back(Pending::Subtree(&node.left));
+                        }
+                        self.pending.push_back(Pending::Entry(&node.key, &node.value));
+                        if !node.right.is_empty() {
+                            self.pending.push_back(Pending::Subtree(&node.right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'map,K,V> ExactSizeIterator for HiMapIterator<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> FusedIterator for HiMapIterator<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> IntoIterator for &'map HiMap<K,V>
+    where K: Ord
+{
+    type Item = (&'map K, &'map V);
+    type IntoIter = HiMapIterator<'map,K,V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+/// Iterator over `(&K,&mut V)` pairs of a `HiMap` in an index range.
+/// Returned by [iter_mut](`HiMap<K,V>::iter_mut`) and [range_by_index_mut](`HiMap<K,V>::range_by_index_mut`).
+///
+/// Like `HiSetIteratorMut`, it keeps the part of the tree not visited yet as a sequence of disjoint borrows
+/// of single entries and whole subtrees, so no two returned borrows can point to the same value.
+pub struct HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{
+    pending: VecDeque<PendingMut<'map,K,V>>,
+    start:  usize,
+    end:    usize,
+}
+
+/// Part of the tree not visited yet by `HiMapIteratorMut`, in order: either a single entry or a whole subtree.
+enum PendingMut<'map,K,V>
+    where K: Ord
+{
+    Entry(&'map K, &'map mut V),
+    Subtree(&'map mut Ref<K,V>),
+}
+
+impl <'map,K,V> HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{
+    /// Create iterator over entries with index in `start..end` within subtree pointed to by `root`.
+    fn new(root: &'map mut Ref<K,V>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.pending_range_mut(start, end, &mut pending);
+        }
+        HiMapIteratorMut { pending, start, end }
+    }
+
+    /// Turn into iterator over mutable values only.
+    pub fn values(self) -> HiMapValuesMut<'map,K,V> {
+        HiMapValuesMut { inner: self }
+    }
+}
+
+impl <'map,K,V> Iterator for HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{
+    type Item = (&'map K, &'map mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                PendingMut::Entry(key, value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                PendingMut::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree into disjoint borrows, continue with its left part
+                        let Node { key, value, left, right } = subtree.node_mut().unwrap();    // only non-empty subtrees are pending
+                        if !right.is_empty() {
+                            self.pending.push_front(PendingMut::Subtree(right));
+                        }
+                        self.pending.push_front(PendingMut::Entry(key, value));
+                        if !left.is_empty() {
+                            self.pending.push_front(PendingMut::Subtree(left));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'map,K,V> DoubleEndedIterator for HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                PendingMut::Entry(key, value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                PendingMut::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree into disjoint borrows, continue with its right part
+                        let Node { key, value, left, right } = subtree.node_mut().unwrap();    // only non-empty subtrees are pending
+                        if !left.is_empty() {
+                            self.pending.push_back(PendingMut::Subtree(left));
+                        }
+                        self.pending.push_back(PendingMut::Entry(key, value));
+                        if !right.is_empty() {
+                            self.pending.push_back(PendingMut::Subtree(right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'map,K,V> ExactSizeIterator for HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> FusedIterator for HiMapIteratorMut<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> IntoIterator for &'map mut HiMap<K,V>
+    where K: Ord
+{
+    type Item = (&'map K, &'map mut V);
+    type IntoIter = HiMapIteratorMut<'map,K,V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+
+/// Iterator over `&K` of a `HiMap` in an index range.
+/// Returned by [keys](`HiMap<K,V>::keys`) and [HiMapIterator::keys].
+pub struct HiMapKeys<'map,K,V>
+    where K: Ord
+{
+    inner: HiMapIterator<'map,K,V>,
+}
+
+impl <'map,K,V> Iterator for HiMapKeys<'map,K,V>
+    where K: Ord
+{
+    type Item = &'map K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n).map(|(key, _)| key )
+    }
+}
+
+impl <'map,K,V> DoubleEndedIterator for HiMapKeys<'map,K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(key, _)| key )
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth_back(n).map(|(key, _)| key )
+    }
+}
+
+impl <'map,K,V> ExactSizeIterator for HiMapKeys<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> FusedIterator for HiMapKeys<'map,K,V>
+    where K: Ord
+{}
+
+
+/// Iterator over `&V` of a `HiMap` in an index range.
+/// Returned by [values](`HiMap<K,V>::values`) and [HiMapIterator::values].
+pub struct HiMapValues<'map,K,V>
+    where K: Ord
+{
+    inner: HiMapIterator<'map,K,V>,
+}
+
+impl <'map,K,V> Iterator for HiMapValues<'map,K,V>
+    where K: Ord
+{
+    type Item = &'map V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, value)| value )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n).map(|(_, value)| value )
+    }
+}
+
+impl <'map,K,V> DoubleEndedIterator for HiMapValues<'map,K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, value)| value )
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth_back(n).map(|(_, value)| value )
+    }
+}
+
+impl <'map,K,V> ExactSizeIterator for HiMapValues<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> FusedIterator for HiMapValues<'map,K,V>
+    where K: Ord
+{}
+
+
+/// Iterator over `&mut V` of a `HiMap` in an index range.
+/// Returned by [values_mut](`HiMap<K,V>::values_mut`) and [HiMapIteratorMut::values].
+pub struct HiMapValuesMut<'map,K,V>
+    where K: Ord
+{
+    inner: HiMapIteratorMut<'map,K,V>,
+}
+
+impl <'map,K,V> Iterator for HiMapValuesMut<'map,K,V>
+    where K: Ord
+{
+    type Item = &'map mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, value)| value )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth(n).map(|(_, value)| value )
+    }
+}
+
+impl <'map,K,V> DoubleEndedIterator for HiMapValuesMut<'map,K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, value)| value )
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        self.inner.nth_back(n).map(|(_, value)| value )
+    }
+}
+
+impl <'map,K,V> ExactSizeIterator for HiMapValuesMut<'map,K,V>
+    where K: Ord
+{}
+
+impl <'map,K,V> FusedIterator for HiMapValuesMut<'map,K,V>
+    where K: Ord
+{}
+
+
+/// Iterator over owned `(K,V)` pairs of a `HiMap`, consuming the map.
+pub struct HiMapOwnedIterator<K,V>
+    where K: Ord
+{
+    pending: VecDeque<PendingOwned<K,V>>,
+    start:  usize,
+    end:    usize,
+}
+
+/// Part of the tree not visited yet by `HiMapOwnedIterator`, in order: either a single owned entry or a whole subtree.
+enum PendingOwned<K,V>
+    where K: Ord
+{
+    Entry(K,V),
+    Subtree(Ref<K,V>),
+}
+
+impl <K,V> HiMapOwnedIterator<K,V>
+    where K: Ord
+{
+    /// Create iterator over entrys with index in `start..end` within subtree `root`, dropping all others.
+    fn new(root: Ref<K,V>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.into_pending_range(start, end, &mut pending);
+        }
+        HiMapOwnedIterator { pending, start, end }
+    }
+}
+
+impl <K,V> Iterator for HiMapOwnedIterator<K,V>
+    where K: Ord
+{
+    type Item = (K,V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Skipped subtrees are dropped whole without visiting them in order.
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                PendingOwned::Entry(key, value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                PendingOwned::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // drop the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree, continue with its left part
+                        let Node { key, value, left, right } = *subtree.node.unwrap();    // only non-empty subtrees are pending
+                        if !right.is_empty() {
+                            self.pending.push_front(PendingOwned::Subtree(right));
+                        }
+                        self.pending.push_front(PendingOwned::Entry(key, value));
+                        if !left.is_empty() {
+                            self.pending.push_front(PendingOwned::Subtree(left));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <K,V> DoubleEndedIterator for HiMapOwnedIterator<K,V>
+    where K: Ord
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Skipped subtrees are dropped whole.
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                PendingOwned::Entry(key, value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some((key, value))
+                    }
+                    to_skip -= 1;
+                },
+                PendingOwned::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // drop the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree, continue with its right part
+                        let Node { key, value, left, right } = *subtree.node.unwrap();    // only non-empty subtrees are pending
+                        if !left.is_empty() {
+                            self.pending.push_back(PendingOwned::Subtree(left));
+                        }
+                        self.pending.push_back(PendingOwned::Entry(key, value));
+                        if !right.is_empty() {
+                            self.pending.push_back(PendingOwned::Subtree(right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <K,V> ExactSizeIterator for HiMapOwnedIterator<K,V>
+    where K: Ord
+{}
+
+impl <K,V> FusedIterator for HiMapOwnedIterator<K,V>
+    where K: Ord
+{}
+
+impl <K,V> IntoIterator for HiMap<K,V>
+    where K: Ord
+{
+    type Item = (K,V);
+    type IntoIter = HiMapOwnedIterator<K,V>;
+
+    /// Turn `HiMap<K,V>` into an `Iterator` of owned `(K,V)` pairs
+    /// ```
+    ///  # use hitree::himap::HiMap;
+    /// let mut m = HiMap::<String,i32>::new();
+    /// m.insert("b", 2);
+    /// m.insert("a", 1);
+    ///
+    /// let mut i = m.into_iter();
+    /// assert_eq!(i.next(), Some(("a".to_string(), 1)));
+    /// assert_eq!(i.next(), Some(("b".to_string(), 2)));
+    /// assert_eq!(i.next(), None);
+    /// ```
+    fn into_iter(self) -> Self::IntoIter {
+        let end = self.root.count;
+        HiMapOwnedIterator::new(self.root, 0, end)
+    }
+}
+
+
+//---------------- Entry -----------------------------------------------------
+
+/// View into a single entry of a `HiMap`, which may be occupied or vacant.
+/// Returned by [entry](`HiMap<K,V>::entry`).
+pub enum Entry<'map,K,V>
+    where K: Ord
+{
+    /// The key is in the map.
+    Occupied(OccupiedEntry<'map,K,V>),
+    /// The key is not in the map.
+    Vacant(VacantEntry<'map,K,V>),
+}
+
+/// View into an occupied entry of a `HiMap`.
+pub struct OccupiedEntry<'map,K,V>
+    where K: Ord
+{
+    map:    &'map mut HiMap<K,V>,
+    index:  usize,
+}
+
+/// View into a vacant entry of a `HiMap`, holding the key until a value is inserted.
+pub struct VacantEntry<'map,K,V>
+    where K: Ord
+{
+    map:    &'map mut HiMap<K,V>,
+    key:    K,
+    index:  usize,
+}
+
+impl <'map,K,V> Entry<'map,K,V>
+    where K: Ord
+{
+    /// Return index of the entry in the map.
+    /// For a vacant entry, this is the index the value will have once inserted.
+    pub fn index(&self) -> usize {
+        match self {
+            Entry::Occupied(entry) => entry.index(),
+            Entry::Vacant(entry) => entry.index(),
+        }
+    }
+
+    /// Borrow the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Insert `default` if the entry is vacant and return mutable borrow of the value.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     assert_eq!(*map.entry("a").or_insert(1), 1);
+    ///     assert_eq!(*map.entry("a").or_insert(2), 1);
+    /// ```
+    pub fn or_insert(self, default: V) -> &'map mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Insert value returned by `default` if the entry is vacant and return mutable borrow of the value.
+    /// `default` is only called for vacant entries.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,String>::new();
+    ///     map.entry("a").or_insert_with(|| "first".to_string());
+    ///     map.entry("a").or_insert_with(|| unreachable!());
+    ///     assert_eq!(map.get("a").map(|s| s.as_str()), Some("first"));
+    /// ```
+    pub fn or_insert_with<F>(self, default: F) -> &'map mut V
+        where F: FnOnce() -> V
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Insert value returned by `default` called with the key if the entry is vacant
+    /// and return mutable borrow of the value.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,usize>::new();
+    ///     assert_eq!(*map.entry("four").or_insert_with_key(|key| key.len()), 4);
+    /// ```
+    pub fn or_insert_with_key<F>(self, default: F) -> &'map mut V
+        where F: FnOnce(&K) -> V
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            },
+        }
+    }
+
+    /// Insert default value of `V` if the entry is vacant and return mutable borrow of the value.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,Vec<i32>>::new();
+    ///     map.entry("a").or_default().push(1);
+    ///     map.entry("a").or_default().push(2);
+    ///     assert_eq!(map.get("a"), Some(&vec![1,2]));
+    /// ```
+    pub fn or_default(self) -> &'map mut V
+        where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Call `f` with mutable borrow of the value if the entry is occupied, return the entry.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::HiMap;
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.entry("a").and_modify(|v| *v += 1).or_insert(0);
+    ///     assert_eq!(map.get("a"), Some(&0));
+    ///     map.entry("a").and_modify(|v| *v += 1).or_insert(0);
+    ///     assert_eq!(map.get("a"), Some(&1));
+    /// ```
+    pub fn and_modify<F>(mut self, f: F) -> Self
+        where F: FnOnce(&mut V)
+    {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl <'map,K,V> OccupiedEntry<'map,K,V>
+    where K: Ord
+{
+    /// Return index of the entry in the map.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Borrow the key of the entry.
+    pub fn key(&self) -> &K {
+        self.map.get_by_index(self.index).unwrap().0     // entry exists for as long as we borrow the map
+    }
+
+    /// Borrow the value of the entry.
+    pub fn get(&self) -> &V {
+        self.map.get_by_index(self.index).unwrap().1
+    }
+
+    /// Borrow mutably the value of the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map.get_by_index_mut(self.index).unwrap().1
+    }
+
+    /// Turn the entry into mutable borrow of the value with the lifetime of the map borrow.
+    pub fn into_mut(self) -> &'map mut V {
+        self.map.get_by_index_mut(self.index).unwrap().1
+    }
+
+    /// Replace the value of the entry and return the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove the entry from the map and return its value.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::{HiMap, Entry};
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.insert("a", 1);
+    ///     map.insert("b", 2);
+    ///     if let Entry::Occupied(entry) = map.entry("a") {
+    ///         assert_eq!(entry.index(), 0);
+    ///         assert_eq!(entry.remove(), 1);
+    ///     }
+    ///     assert_eq!(map.len(), 1);
+    /// ```
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Remove the entry from the map and return its key and value.
+    pub fn remove_entry(self) -> (K,V) {
+        self.map.take_by_index(self.index).unwrap()
+    }
+}
+
+impl <'map,K,V> VacantEntry<'map,K,V>
+    where K: Ord
+{
+    /// Return index the entry will have in the map once a value is inserted.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Borrow the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take back ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert value into the map and return mutable borrow of it.
+    /// The position of the entry is already known, so inserting takes a single descent from the root without comparing
+    /// keys, and the value is returned without looking it up again.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::himap::{HiMap, Entry};
+    ///     let mut map = HiMap::<&str,i32>::new();
+    ///     map.insert("a", 1);
+    ///     map.insert("c", 3);
+    ///     if let Entry::Vacant(entry) = map.entry("b") {
+    ///         assert_eq!(entry.index(), 1);
+    ///         *entry.insert(1) += 1;
+    ///     }
+    ///     assert_eq!(map.get_by_index(1), Some((&"b", &2)));
+    /// ```
+    pub fn insert(self, value: V) -> &'map mut V {
+        self.map.root.insert_new(self.index, Node::new(self.key, value))
+    }
+}
+
+
+//---------------- Ref -------------------------------------------------------
+
+impl <K,V> Ref<K,V>
+    where K: Ord
+{
+
+    fn to(node: Box<Node<K,V>>) -> Ref<K,V> {
+        let count = 1 + node.left.count + node.right.count;
+        Ref { count, node: Some(node) }
+    }
+
+
+    /// Build perfectly balanced subtree of `count` entries taken in order from the iterator.
+    fn from_sorted(entries: &mut impl Iterator<Item=(K,V)>, count: usize) -> Ref<K,V> {
+        if count == 0 {
+            Ref::default()
+        } else {
+            let left_count = (count - 1) / 2;
+            let left = Ref::from_sorted(entries, left_count);
+            let (key, value) = entries.next().unwrap();    // caller guarantees there is enough entries
+            let right = Ref::from_sorted(entries, count - 1 - left_count);
+            Ref::to(Box::new(Node { key, value, left, right }))
+        }
+    }
+
+
+    fn node(&self) -> Option<&Node<K,V>> {
+        self.node.as_deref()
+    }
+
+    fn node_mut(&mut self) -> Option<&mut Node<K,V>> {
+        self.node.as_deref_mut()
+    }
+
+
+    fn take(&mut self) -> Ref<K,V> {
+        std::mem::take(&mut *self)
+    }
+
+    fn take_left_subtree(&mut self) -> Ref<K,V> {
+        match self.node_mut() {
+            None => Ref::default(),
+            Some(node) => {
+                let left = node.left.take();
+                self.count -= left.count;
+                left
+            },
+        }
+    }
+
+    fn take_right_subtree(&mut self) -> Ref<K,V> {
+        match self.node_mut() {
+            None => Ref::default(),
+            Some(node) => {
+                let right = node.right.take();
+                self.count -= right.count;
+                right
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.node.is_none()
+    }
+
+    #[inline]
+    fn balance(&self) -> isize {
+        self.node.as_deref().unwrap().balance()     // balance only makes sense if there is a node, hence unwrap()
+    }
+
+
+    fn set_left(&mut self, subtree: Ref<K,V>) {
+        let node = self.node_mut().unwrap();
+        node.left = subtree;
+        self.count = node.count();
+    }
+
+    fn set_right(&mut self, subtree: Ref<K,V>) {
+        let node = self.node_mut().unwrap();
+        node.right = subtree;
+        self.count = node.count();
+    }
+
+    /// See `HiSet` `Ref::rotate_left` for illustration
+    #[inline]
+    fn rotate_left(&mut self) {
+        let mut old_root = self.take();
+        let mut new_root = old_root.take_right_subtree();
+        let mid_subtree = new_root.take_left_subtree();
+        old_root.set_right(mid_subtree);
+        new_root.set_left(old_root);
+        *self = new_root;
+    }
+
+    /// See `HiSet` `Ref::rotate_right` for illustration
+    #[inline]
+    fn rotate_right(&mut self) {
+        let mut old_root = self.take();
+        let mut new_root = old_root.take_left_subtree();
+        let mid_subtree = new_root.take_right_subtree();
+        old_root.set_left(mid_subtree);
+        new_root.set_right(old_root);
+        *self = new_root;
+    }
+
+
+    /// Append parts of the subtree with index in `start..end` to `pending` in order,
+    /// as whole subtrees where possible and single entries along the paths to `start` and `end`.
+    fn pending_range<'map>(&'map self, start: usize, end: usize, pending: &mut VecDeque<Pending<'map,K,V>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(Pending::Subtree(self));
+            }
+        } else if let Some(node) = self.node() {
+            let index = node.left.count;
+            if start < index {
+                node.left.pending_range(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(Pending::Entry(&node.key, &node.value));
+            }
+            if end > index + 1 {
+                node.right.pending_range(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+
+    /// Mutable version of `pending_range`. Appended parts are disjoint borrows of the subtree.
+    fn pending_range_mut<'map>(&'map mut self, start: usize, end: usize, pending: &mut VecDeque<PendingMut<'map,K,V>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(PendingMut::Subtree(self));
+            }
+        } else if let Some(node) = self.node_mut() {
+            let Node { key, value, left, right } = node;
+            let index = left.count;
+            if start < index {
+                left.pending_range_mut(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(PendingMut::Entry(key, value));
+            }
+            if end > index + 1 {
+                right.pending_range_mut(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+
+
+    /// Owned version of `pending_range`. Parts of the subtree outside of `start..end` are dropped.
+    fn into_pending_range(self, start: usize, end: usize, pending: &mut VecDeque<PendingOwned<K,V>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(PendingOwned::Subtree(self));
+            }
+        } else if let Some(node) = self.node {
+            let Node { key, value, left, right } = *node;
+            let index = left.count;
+            if start < index {
+                left.into_pending_range(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(PendingOwned::Entry(key, value));
+            }
+            if end > index + 1 {
+                right.into_pending_range(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+
+
+    /// Find node by key reference
+    fn find<KEY>(&self, key: &KEY) -> Option<&Node<K,V>>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        let mut current_node = self.node();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match Ord::cmp(node.key.borrow(), key) {
+                        Ordering::Greater => {
+                            // key must be in the left subtree
+                            current_node = node.left.node();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some(node)
+                        },
+                        Ordering::Less => {
+                            // key must be in the right subtree
+                            current_node = node.right.node();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// Find index of node by key reference.
+    /// Returns `Ok(index)` if the key was found, or `Err(index)` with the index a node with this key would have
+    /// if it was inserted.
+    fn find_index<KEY>(&self, key: &KEY) -> Result<usize,usize>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        let mut current_node = self.node();
+        let mut current_index_shift = 0;
+        loop {
+            match current_node {
+                None => return Err(current_index_shift),
+                Some(node) => {
+                    match Ord::cmp(node.key.borrow(), key) {
+                        Ordering::Greater => {
+                            // key must be in the left subtree
+                            current_node = node.left.node();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Ok(current_index_shift + node.left.count)
+                        },
+                        Ordering::Less => {
+                            // key must be in the right subtree
+                            current_node = node.right.node();
+                            current_index_shift += 1 + node.left.count;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// Find the closest node below (or above if `below` is false) the key, or the node equal to the key if
+    /// `inclusive` is set. Return its index along with the node.
+    fn find_closest<KEY>(&self, key: &KEY, below: bool, inclusive: bool) -> Option<(usize,&Node<K,V>)>
+        where KEY: ?Sized + Ord, K: Borrow<KEY>
+    {
+        let mut current_node = self.node();
+        let mut current_index_shift = 0;
+        let mut closest = None;
+        loop {
+            match current_node {
+                None => return closest,
+                Some(node) => {
+                    let index = current_index_shift + node.left.count;
+                    match (Ord::cmp(node.key.borrow(), key), below) {
+                        (Ordering::Equal, _) if inclusive => {
+                            // exact match is the closest one
+                            return Some((index, node))
+                        },
+                        (Ordering::Less, true) => {
+                            // candidate, but there may be a closer one in the right subtree
+                            closest = Some((index, node));
+                            current_node = node.right.node();
+                            current_index_shift = index + 1;
+                        },
+                        (Ordering::Greater, false) => {
+                            // candidate, but there may be a closer one in the left subtree
+                            closest = Some((index, node));
+                            current_node = node.left.node();
+                        },
+                        (Ordering::Less, false) | (Ordering::Equal, false) => {
+                            current_node = node.right.node();
+                            current_index_shift = index + 1;
+                        },
+                        (Ordering::Greater, true) | (Ordering::Equal, true) => {
+                            current_node = node.left.node();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// insert is recursive as it needs to balance the tree on the way back up.
+    /// Returns old value if the key was already present, in which case only the value is replaced.
+    fn insert(&mut self, new_node: Box<Node<K,V>>) -> Option<V> {
+        match self.node_mut() {
+            None => {   // there are no nodes in subtree rooted at this Ref.
+                *self = Ref::to(new_node);
+                None    // we have inserted a new entry
+            },
+            Some(node) => {     // There is at least one node
+                match Ord::cmp(&node.key,&new_node.key) {
+                    Ordering::Equal => {
+                        // already in there, replace the value
+                        Some(std::mem::replace(&mut node.value, new_node.value))
+                    },
+                    Ordering::Less => { // insert into right subtree
+                        let old_value = node.right.insert(new_node);
+                        if old_value.is_none() {
+                            self.count += 1;    // increase number of entries for subtree
+                            if self.balance() > 1 { // too right heavy
+                                self.rotate_left();
+                            }
+                        }
+                        old_value
+                    },
+                    Ordering::Greater => {
+                        let old_value = node.left.insert(new_node);
+                        if old_value.is_none() {
+                            self.count += 1;    // increase number of entries for subtree
+                            if self.balance() < -1 {    // too left heavy
+                                self.rotate_right();
+                            }
+                        }
+                        old_value
+                    }
+                }
+            }
+        }
+    }
+
+    /// Insert node with a key known not to be in the subtree yet at given index, and return mutable borrow of its value.
+    /// Balances the subtree the same way as `insert`. As the subtree is sure to grow, whether a node has to be rotated
+    /// is known before descending below it, so the value only has to be found again below a rotated node.
+    fn insert_new(&mut self, index: usize, new_node: Box<Node<K,V>>) -> &mut V {
+        let (left_count, right_count) = match self.node() {
+            None => {
+                *self = Ref::to(new_node);
+                return &mut self.node_mut().unwrap().value
+            },
+            Some(node) => (node.left.count, node.right.count),
+        };
+        let into_left = index <= left_count;
+        let needs_rotation = if into_left {
+            tree_height(right_count) - tree_height(left_count + 1) < -1
+        } else {
+            tree_height(right_count + 1) - tree_height(left_count) > 1
+        };
+        if needs_rotation {
+            self.insert(new_node);
+            return &mut self.node_by_index_mut(index).unwrap().value
+        }
+        self.count += 1;
+        let node = self.node_mut().unwrap();
+        if into_left {
+            node.left.insert_new(index, new_node)
+        } else {
+            node.right.insert_new(index - left_count - 1, new_node)
+        }
+    }
+
+    /// Find node by index and borrow it mutably.
+    fn node_by_index_mut(&mut self, index: usize) -> Option<&mut Node<K,V>> {
+        let mut index_to_find = index;
+        let mut current_node = self.node_mut();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match node.left.count.cmp(&index_to_find) {
+                        Ordering::Greater => {
+                            // index must be in the left subtree
+                            current_node = node.left.node_mut();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some(node)
+                        },
+                        Ordering::Less => {
+                            // index must be in the right subtree
+                            index_to_find = index_to_find - 1 - node.left.count;
+                            current_node = node.right.node_mut();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove leftmost node from the subtree.
+    fn take_leftmost_node(&mut self) -> Option<Box<Node<K,V>>> {
+        match self.node_mut() {
+            None => None,   // no node here, tell caller to remove his node
+            Some(node) => {
+                match node.left.take_leftmost_node() {
+                    None => {
+                        // there is no left node, we are the node to remove!
+                        let mut removed_node = self.node.take().unwrap();
+                        *self = removed_node.right.take();
+                        Some(removed_node)
+                    },
+                    Some(removed_node) => {
+                        self.count -= 1;    // one node has been removed
+                        if self.balance() > 1 {     // if we are too right leaning now, restore balance
+                            self.rotate_left();
+                        }
+                        Some(removed_node)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove rightmost node from the subtree.
+    fn take_rightmost_node(&mut self) -> Option<Box<Node<K,V>>> {
+        match self.node_mut() {
+            None => None,   // no node here, tell caller to remove his node
+            Some(node) => {
+                match node.right.take_rightmost_node() {
+                    None => {
+                        // there is no right node, we are the node to remove!
+                        let mut removed_node = self.node.take().unwrap();
+                        *self = removed_node.left.take();
+                        Some(removed_node)
+                    },
+                    Some(removed_node) => {
+                        self.count -= 1;    // one node has been removed
+                        if self.balance() < -1 {     // if we are too left leaning now, restore balance
+                            self.rotate_right();
+                        }
+                        Some(removed_node)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the node this `Ref` points to, replacing it with a node from its subtrees.
+    fn take_this_node(&mut self) -> Option<Box<Node<K,V>>> {
+        let node = self.node_mut()?;
+        match (node.left.is_empty(), node.right.is_empty()) {
+            (true, true) => {    // leaf node, can be removed directly without consequences
+                self.node.take()
+            },
+            (false, true) => {   // there is a left subtree, move it up
+                let mut removed_node = self.node.take().unwrap();
+                *self = removed_node.left.take();
+                Some(removed_node)
+            },
+            (true, false) => {   // there is a right subtree, move it up
+                let mut removed_node = self.node.take().unwrap();
+                *self = removed_node.right.take();
+                Some(removed_node)
+            }
+            (false, false) => {  // there are two subtrees, take the closest node from the one with more nodes and replace the removed node with it
+                let mut removed_node = self.node.take().unwrap();
+                let mut left_subtree = removed_node.left.take();
+                let mut right_subtree = removed_node.right.take();
+                let mut new_subtree_root_node = if left_subtree.count > right_subtree.count {
+                    left_subtree.take_rightmost_node().unwrap()
+                } else {
+                    right_subtree.take_leftmost_node().unwrap()
+                };
+                new_subtree_root_node.left = left_subtree;
+                new_subtree_root_node.right = right_subtree;
+                *self = Ref::to(new_subtree_root_node);
+                // balance should not be an issue, we took from the bigger one
+                Some(removed_node)
+            }
+        }
+    }
+
+    fn take_node_by_key<KEY>(&mut self, key: &KEY) -> Option<Box<Node<K,V>>>
+        where KEY: ?Sized + Ord,
+            K: Borrow<KEY>
+    {
+        let res = if let Some(node) = self.node_mut() {
+            match Ord::cmp(node.key.borrow(), key) {
+                Ordering::Equal => {    // this is the node to remove
+                    self.take_this_node()
+                },
+                Ordering::Less => {     // node must be in the right subtree
+                    node.right.take_node_by_key(key)
+                },
+                Ordering::Greater => {  // node must be in the left subtree
+                    node.left.take_node_by_key(key)
+                }
+            }
+        } else {
+            None
+        };
+        if res.is_some() {
+            self.rebalance();
+        }
+        res
+    }
+
+    fn take_node_by_index(&mut self, index_to_take: usize) -> Option<Box<Node<K,V>>> {
+        let res = if let Some(node) = self.node_mut() {
+            let index_of_this_node = node.left.count;
+            match Ord::cmp(&index_of_this_node, &index_to_take) {
+                Ordering::Equal => {    // this is the node to remove
+                    self.take_this_node()
+                },
+                Ordering::Less => {     // node must be in the right subtree
+                    node.right.take_node_by_index(index_to_take - index_of_this_node - 1)
+                },
+                Ordering::Greater => {  // node must be in the left subtree
+                    node.left.take_node_by_index(index_to_take)
+                }
+            }
+        } else {
+            None
+        };
+        if res.is_some() {
+            self.rebalance();
+        }
+        res
+    }
+
+    fn rebalance(&mut self) {
+        if let Some(node) = self.node() {
+            self.count = node.count();
+            let balance = self.balance();
+            if balance < -1 {
+                self.rotate_right();
+            } else if balance > 1 {
+                self.rotate_left();
+            }
+        } else {
+            self.count = 0;
+        };
+    }
+}
+
+impl <K,V> Default for Ref<K,V>
+    where K: Ord
+{
+    /// Empty reference
+    fn default() -> Self {
+        Self { count: 0, node: None }
+    }
+}
+
+
+//--------------- Node ------------------------------------------------------------
+
+
+impl <K,V> Node<K,V>
+    where K: Ord
+{
+    /// Creates a new Node with given key, value and empty left & right refs
+    fn new(key: impl Into<K>, value: V) -> Box<Node<K,V>> {
+        Box::new( Node { key: key.into(), value, left: Ref::default(), right: Ref::default() } )
+    }
+
+    /// Calculate number of nodes including this node and any subtrees pointed to by left & right
+    fn count(&self) -> usize {
+        self.left.count + self.right.count + 1
+    }
+
+    /// returns difference in height between right and left subtrees. >0 right is bigger, <0 left is bigger.
+    #[inline]
+    fn balance(&self) -> isize {
+        tree_height(self.right.count) - tree_height(self.left.count)
+    }
+
+}
@@ -1,33 +1,72 @@
-//use std::fmt::{Debug,Display,Formatter};
+use std::fmt::{Debug,Formatter};
+use std::hash::{Hash,Hasher};
 use std::borrow::{Borrow, BorrowMut};
 use std::cmp::Ordering;
-use super::tree_height;
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+use super::{tree_height, index_bounds};
+use super::augment::{Augment, Weight, Weighted};
+use super::compare::{Comparator, OrdComparator};
 
 /// Ordered set of values, accessible by value or index of value in the set.
 /// Stores values in a balanced binary tree with subtree node count tracking.
 /// Nodes are allocated on the heap using `Box`.
-pub struct HiSet<T: Ord> {
-    root: Ref<T>,
+///
+/// Optionally keeps an aggregate of values for every subtree, as defined by the [Augment] type `A`.
+/// Values in an augmented set can't be borrowed mutably, as that would invalidate the aggregates.
+///
+/// Values are ordered by their `Ord` implementation, unless a [Comparator] `C` is given with [with_comparator](`HiSet<T,(),C>::with_comparator`).
+pub struct HiSet<T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    root: Ref<T,A>,
+    comparator: C,
 }
 
 /// Reference to a subtree of `Node`s, including node count of subtree pointed to by it.
-struct Ref<T>
-    where T: Ord
+struct Ref<T,A = ()>
+    where A: Augment<T>
 {
     count: usize,
-    node: Option<Box<Node<T>>>,
+    node: Option<Box<Node<T,A>>>,
 }
 
-/// Node holding a value and references to the left (lesser) and right (greater) subtrees.
+/// Node holding a value, references to the left (lesser) and right (greater) subtrees,
+/// and the aggregate of all values in the subtree rooted at this node.
 /// Left and right subtrees are always balanced - they may differ by at most one level of depth,
 /// and all the inner nodes of the tree (all levels except the one furthest from the root)
 /// must contain both left and right subtrees that are also balanced.
-struct Node<T>
-    where T: Ord
+struct Node<T,A = ()>
+    where A: Augment<T>
 {
     value: T,
-    left: Ref<T>,
-    right: Ref<T>,
+    left: Ref<T,A>,
+    right: Ref<T,A>,
+    aggregate: A::Value,
+}
+
+impl <T,A,C> Clone for HiSet<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    fn clone(&self) -> Self {
+        HiSet { root: self.root.clone(), comparator: self.comparator.clone() }
+    }
+}
+
+impl <T,A> Clone for Ref<T,A>
+    where T: Clone, A: Augment<T>
+{
+    fn clone(&self) -> Self {
+        Ref { count: self.count, node: self.node.clone() }
+    }
+}
+
+impl <T,A> Clone for Node<T,A>
+    where T: Clone, A: Augment<T>
+{
+    fn clone(&self) -> Self {
+        Node { value: self.value.clone(), left: self.left.clone(), right: self.right.clone(), aggregate: self.aggregate.clone() }
+    }
 }
 
 
@@ -47,14 +86,121 @@ impl <T> HiSet<T>
     ///     let mut set = HiSet::<String>::new();
     /// ```
     pub fn new() -> HiSet<T> {
-        HiSet { root: Ref::default() }
+        HiSet { root: Ref::default(), comparator: OrdComparator }
+    }
+
+    /// Create a `HiSet` from values given in strictly ascending order.
+    ///
+    /// Builds a perfectly balanced tree in O(n) without any comparisons beyond checking the order.
+    /// Returns `None` if the values are not strictly ascending.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from_sorted_iter(0..1000).unwrap();
+    ///     assert_eq!(set.len(), 1000);
+    ///     assert_eq!(set.get_by_index(500), Some(&500));
+    ///
+    ///     assert!(HiSet::<i32>::from_sorted_iter([1,3,2]).is_none());
+    ///     assert!(HiSet::<i32>::from_sorted_iter([1,2,2]).is_none());
+    /// ```
+    pub fn from_sorted_iter(values: impl IntoIterator<Item=T>) -> Option<HiSet<T>> {
+        let values: Vec<T> = values.into_iter().collect();
+        if values.windows(2).all(|pair| pair[0] < pair[1]) {
+            Some(HiSet { root: Ref::from_sorted_vec(values), comparator: OrdComparator })
+        } else {
+            None
+        }
+    }
+
+    /// Create a `HiSet` from a `Vec` of values in any order.
+    ///
+    /// Sorts the values, removes duplicates keeping the first one of equal values, and builds a perfectly balanced
+    /// tree from the result. Much faster than inserting the values one by one.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from_vec(vec![5,3,1,3,4,2,5]);
+    ///     assert!(set.iter().eq([1,2,3,4,5].iter()));
+    /// ```
+    pub fn from_vec(values: Vec<T>) -> HiSet<T> {
+        HiSet::from_vec_with_comparator(values, OrdComparator)
     }
+}
 
 
+impl <T,C> HiSet<T,(),C>
+    where C: Comparator<T>
+{
+    /// Create new empty `HiSet` ordering its values by given comparator instead of their `Ord` implementation.
+    /// The comparator can be any closure comparing two `&T`, or a type implementing [Comparator].
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let descending = true;  // chosen at runtime
+    ///     let mut set = HiSet::with_comparator(move |a: &i32, b: &i32| if descending { b.cmp(a) } else { a.cmp(b) });
+    ///     set.extend([1,3,2]);
+    ///     assert!(set.iter().eq([3,2,1].iter()));
+    ///     assert_eq!(set.index_of(&1), Some(2));
+    /// ```
+    pub fn with_comparator(comparator: C) -> HiSet<T,(),C> {
+        HiSet { root: Ref::default(), comparator }
+    }
+}
+
 
+impl <T,A,C> HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Create new empty `HiSet` keeping aggregates given by augment `A` and ordering its values by given comparator.
+    /// Use [with_comparator](`HiSet<T,(),C>::with_comparator`) for sets without an augment.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     use hitree::augment::Augment;
+    ///
+    ///     struct Sum;
+    ///
+    ///     impl Augment<u32> for Sum {
+    ///         type Value = u32;
+    ///         fn leaf(value: &u32) -> u32 { *value }
+    ///         fn combine(left: u32, right: u32) -> u32 { left + right }
+    ///     }
+    ///
+    ///     let mut set = HiSet::<u32,Sum,_>::with_augment_and_comparator(|a: &u32, b: &u32| b.cmp(a) );
+    ///     set.extend([1,5,3,2]);
+    ///     assert!(set.iter().eq([5,3,2,1].iter()));
+    ///     assert_eq!(set.aggregate_by_index(..2), Some(8));
+    /// ```
+    pub fn with_augment_and_comparator(comparator: C) -> HiSet<T,A,C> {
+        HiSet { root: Ref::default(), comparator }
+    }
 
+    /// Sort the values by the comparator, remove duplicates keeping the first one of equal values, and build the set.
+    fn from_vec_with_comparator(mut values: Vec<T>, comparator: C) -> HiSet<T,A,C> {
+        values.sort_by(|a, b| comparator.compare(a, b) );
+        values.dedup_by(|a, b| comparator.compare(a, b) == Ordering::Equal );
+        HiSet { root: Ref::from_sorted_vec(values), comparator }
+    }
 
+    /// Sort the values by the comparator keeping equal values in their original order, and build the tree
+    /// without removing duplicates. Used by `HiBag`.
+    pub(crate) fn from_vec_with_duplicates(mut values: Vec<T>, comparator: C) -> HiSet<T,A,C> {
+        values.sort_by(|a, b| comparator.compare(a, b) );
+        HiSet { root: Ref::from_sorted_vec(values), comparator }
+    }
 
+    /// Borrow the comparator ordering values of this set.
+    pub fn comparator(&self) -> &C {
+        &self.comparator
+    }
 
 
     /// Return current number of entries in the set.
@@ -72,6 +218,21 @@ impl <T> HiSet<T>
         self.root.count
     }
 
+    /// Return true if the set contains no values.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut hiset = HiSet::<i32>::new();
+    ///     assert!(hiset.is_empty());
+    ///     hiset.insert(1);
+    ///     assert!(!hiset.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
 
     /// Insert a new value into the set.
     /// If the value was not in the set, return true.
@@ -95,7 +256,12 @@ impl <T> HiSet<T>
     ///     assert_eq!(hiset.insert("This can be converted to a String"), true);
     /// ```
     pub fn insert(&mut self, value: impl Into<T>) -> bool {
-        self.root.insert(Node::new(value))
+        self.root.insert(Node::new(value), &self.comparator, false).is_ok()
+    }
+
+    /// Insert a value even if equal values are already in the tree, placing it after them. Used by `HiBag`.
+    pub(crate) fn insert_duplicate(&mut self, value: T) {
+        let _ = self.root.insert(Node::new(value), &self.comparator, true);
     }
 
 
@@ -154,80 +320,10 @@ impl <T> HiSet<T>
         }
     }
 
-    /// Get a mutable borrow of value from set by index.
-    /// Values in the set are sorted according to their Ord trait,
-    /// index 0 is the smallest value.
-    /// Borrowed value can be any mutable reference type that can be borrowed from T.
-    /// WARNING: You must never change the borrowed value in a way that would affect its ordering according to
-    /// its Ord trait implementation!
-    ///
-    /// # Examples:
-    ///
-    /// ```
-    ///     # use std::cmp::Ordering;
-    ///     # use hitree::hiset::HiSet;
-    ///
-    ///     struct TestValue {
-    ///         ordering: String,
-    ///         data: usize,
-    ///     }
-    ///
-    ///     impl TestValue {
-    ///         pub fn new(ordering: impl Into<String>) -> Self { TestValue { ordering: ordering.into(), data: 0 }}
-    ///         pub fn touch(&mut self) { self.data += 1; }
-    ///     }
-    ///     impl PartialEq for TestValue { fn eq(&self, other: &Self) -> bool { self.ordering.eq(&other.ordering) } }
-    ///     impl Eq for TestValue {}
-    ///     impl PartialOrd for TestValue { fn partial_cmp(&self, other: &Self) -> Option<Ordering> { self.ordering.partial_cmp(&other.ordering) } }
-    ///     impl Ord for TestValue { fn cmp(&self, other: &Self) -> Ordering { self.ordering.cmp(&other.ordering) } }
-    ///
-    ///
-    ///     let mut hiset = HiSet::<TestValue>::new();
-    ///     hiset.insert(TestValue::new("first"));
-    ///     hiset.insert(TestValue::new("second"));
-    ///     hiset.insert(TestValue::new("third"));
-    ///
-    ///     hiset.get_by_index_mut(0).map(|value| value.touch() );
-    ///     hiset.get_by_index_mut(2).map(|value| { value.touch(); value.touch();} );
-    ///
-    ///     assert_eq!(hiset.get_by_index(0).unwrap().data, 1);
-    ///     assert_eq!(hiset.get_by_index(1).unwrap().data, 0);
-    ///     assert_eq!(hiset.get_by_index(2).unwrap().data, 2);
-    /// ```
-    ///
-    pub fn get_by_index_mut<B>(&mut self, index: usize) -> Option<&mut B>
-        where T: BorrowMut<B>,
-              B: ?Sized
-    {
-        let mut index_to_find = index;
-        let mut current_node = self.root.node_mut();
-        loop {
-            match current_node {
-                None => return None,
-                Some(node) => {
-                    match node.left.count.cmp(&index_to_find) {
-                        Ordering::Greater => {
-                            // index must be in the left subtree
-                            current_node = node.left.node_mut();
-                        },
-                        Ordering::Equal => {
-                            // found it, its this node
-                            return Some(node.borrow_value_mut())
-                        },
-                        Ordering::Less => {
-                            // index must be in the right subtree
-                            index_to_find = index_to_find - 1 - node.left.count;
-                            current_node = node.right.node_mut();
-                        }
-                    }
-                }
-            }
-        }
-    }
 
 
     /// Borrow a value from the set by a key reference.
-    /// Reference type of key must have the same `Ord` ordering as `&T`.
+    /// Reference type of key must have the same ordering as `&T` under the comparator of the set.
     ///
     /// # Examples:
     /// ```
@@ -242,15 +338,16 @@ impl <T> HiSet<T>
     ///     assert_eq!(set.get("not there"), None);
     ///     assert_eq!(set.get(&"This".to_string()), Some(&"This".to_string()));
     /// ```
-    pub fn get<KEY>(&mut self, key: &KEY) -> Option<&T>
-        where KEY: ?Sized + Ord, T: Borrow<KEY>
+    pub fn get<KEY>(&self, key: &KEY) -> Option<&T>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
     {
+        let comparator = &self.comparator;
         let mut current_node = self.root.node();
         loop {
             match current_node {
                 None => return None,
                 Some(node) => {
-                    match Ord::cmp(node.value.borrow(), key) {
+                    match comparator.compare(node.value.borrow(), key) {
                         Ordering::Greater => {
                             // index must be in the left subtree
                             current_node = node.left.node();
@@ -269,51 +366,30 @@ impl <T> HiSet<T>
         }
     }
 
-    /// Borrow mutably value from set by key reference.
-    /// Reference type of key must have the same `Ord` ordering as `&T`.
+    /// Return true if the set contains a value equal to the key.
+    /// Reference type of key must have the same ordering as `&T` under the comparator of the set.
     ///
     /// # Examples:
     /// ```
     ///     # use hitree::hiset::HiSet;
-    ///     let mut set = HiSet::<String>::new();
-    ///     set.insert("This");
-    ///     set.insert("is");
-    ///     set.insert("a");
-    ///     set.insert("test!");
+    ///     use std::sync::Arc;
+    ///     let set = Arc::new(HiSet::<String>::from(["This","is","a","test!"].into_iter()));
     ///
-    ///     assert_eq!(set.get_mut("test!"), Some(&mut "test!".to_string()));
-    ///     assert_eq!(set.get_mut("not there"), None);
-    ///     assert_eq!(set.get_mut(&"This".to_string()), Some(&mut "This".to_string()));
-    ///```
-    pub fn get_mut<KEY>(&mut self, key: &KEY) -> Option<&mut T>
-        where KEY: ?Sized + Ord, T: Borrow<KEY>
+    ///     // read access only needs a shared reference, so the set can be shared between threads
+    ///     let shared = Arc::clone(&set);
+    ///     let found = std::thread::spawn(move || shared.contains("is") && !shared.contains("not there"));
+    ///     assert!(found.join().unwrap());
+    ///     assert_eq!(set.index_of("test!"), Some(3));
+    /// ```
+    pub fn contains<KEY>(&self, key: &KEY) -> bool
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
     {
-        let mut current_node = self.root.node_mut();
-        loop {
-            match current_node {
-                None => return None,
-                Some(node) => {
-                    match Ord::cmp(node.value.borrow(), key) {
-                        Ordering::Greater => {
-                            // index must be in the left subtree
-                            current_node = node.left.node_mut();
-                        },
-                        Ordering::Equal => {
-                            // found it, its this node
-                            return Some(node.borrow_value_mut::<T>())
-                        },
-                        Ordering::Less => {
-                            // index must be in the right subtree
-                            current_node = node.right.node_mut();
-                        }
-                    }
-                }
-            }
-        }
+        self.root.find_index(key, &self.comparator).is_ok()
     }
 
 
 
+
     /// Find index of value given by key reference.
     ///
     /// # Examples:
@@ -332,97 +408,211 @@ impl <T> HiSet<T>
     ///     assert_eq!(set.index_of("nonexistent"), None);
     ///
     /// ```
-    pub fn index_of<KEY>(&mut self, key: &KEY) -> Option<usize>
-        where KEY: ?Sized + Ord, T: Borrow<KEY>
+    pub fn index_of<KEY>(&self, key: &KEY) -> Option<usize>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
     {
-        let mut current_node = self.root.node();
-        let mut current_index_shift = 0;
-        loop {
-            match current_node {
-                None => return None,
-                Some(node) => {
-                    match Ord::cmp(node.value.borrow(), key) {
-                        Ordering::Greater => {
-                            // index must be in the left subtree
-                            current_node = node.left.node();
-                        },
-                        Ordering::Equal => {
-                            // found it, its this node
-                            return Some(current_index_shift + node.left.count)
-                        },
-                        Ordering::Less => {
-                            // index must be in the right subtree
-                            current_node = node.right.node();
-                            current_index_shift += 1 + node.left.count;
-                        }
-                    }
-                }
-            }
-        }
+        self.root.find_index(key, &self.comparator).ok()
     }
 
 
 
-    /// Remove the smallest value from the set and return it.
-    ///
-    /// Examples:
+    /// Return number of values in the set strictly less than the key.
+    /// The key does not need to be present in the set.
     ///
+    /// # Examples:
     /// ```
     ///     # use hitree::hiset::HiSet;
-    ///     let mut set = HiSet::<i32>::new();
-    ///     set.insert(10);
-    ///     set.insert(15);
-    ///     set.insert(5);
+    ///     let set = HiSet::<i32>::from([10,20,30,40].into_iter());
     ///
-    ///     assert_eq!(set.len(), 3);
-    ///     assert_eq!(set.take_first(), Some(5));
-    ///     assert_eq!(set.len(), 2);
-    ///     assert_eq!(set.take_first(), Some(10));
-    ///     assert_eq!(set.len(), 1);
-    ///     assert_eq!(set.take_first(), Some(15));
-    ///     assert_eq!(set.len(), 0);
-    ///     assert_eq!(set.take_first(), None);
+    ///     assert_eq!(set.rank(&5), 0);
+    ///     assert_eq!(set.rank(&10), 0);
+    ///     assert_eq!(set.rank(&25), 2);
+    ///     assert_eq!(set.rank(&40), 3);
+    ///     assert_eq!(set.rank(&50), 4);
     /// ```
-    ///
-    pub fn take_first(&mut self) -> Option<T> {
-        self.root.take_leftmost_node().map(|node| node.value )
+    pub fn rank<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.lower_bound_index(key)
     }
 
-    /// Remove the largest value from the set and return it.
-    ///
-    /// Examples:
+    /// Return index of the first value not less than the key.
+    /// This is the index of the key if it is present in the set, or the index it would have if it was inserted.
+    /// Returns `len()` if all values are less than the key.
     ///
+    /// # Examples:
     /// ```
     ///     # use hitree::hiset::HiSet;
-    ///     let mut set = HiSet::<i32>::new();
-    ///     set.insert(10);
-    ///     set.insert(15);
-    ///     set.insert(5);
+    ///     let set = HiSet::<i32>::from([10,20,30,40].into_iter());
     ///
-    ///     assert_eq!(set.len(), 3);
-    ///     assert_eq!(set.take_last(), Some(15));
-    ///     assert_eq!(set.len(), 2);
-    ///     assert_eq!(set.take_last(), Some(10));
-    ///     assert_eq!(set.len(), 1);
-    ///     assert_eq!(set.take_last(), Some(5));
-    ///     assert_eq!(set.len(), 0);
-    ///     assert_eq!(set.take_last(), None);
+    ///     assert_eq!(set.lower_bound_index(&5), 0);
+    ///     assert_eq!(set.lower_bound_index(&20), 1);
+    ///     assert_eq!(set.lower_bound_index(&25), 2);
+    ///     assert_eq!(set.lower_bound_index(&50), 4);
+    /// ```
+    pub fn lower_bound_index<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.bound_index(key, &self.comparator, false)
+    }
+
+    /// Return index of the first value greater than the key.
+    /// Returns `len()` if no value is greater than the key.
+    ///
+    /// # Examples:
     /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from([10,20,30,40].into_iter());
     ///
-    pub fn take_last(&mut self) -> Option<T> {
-        self.root.take_rightmost_node().map(|node| node.value )
+    ///     assert_eq!(set.upper_bound_index(&5), 0);
+    ///     assert_eq!(set.upper_bound_index(&20), 2);
+    ///     assert_eq!(set.upper_bound_index(&25), 2);
+    ///     assert_eq!(set.upper_bound_index(&40), 4);
+    /// ```
+    pub fn upper_bound_index<KEY>(&self, key: &KEY) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.bound_index(key, &self.comparator, true)
     }
 
 
-    /// Take an entry by reference to another value and return it.
-    /// Whatever you use as key must give the same Ord results as Ord on &T!
-    ///
-    ///  # Examples:
+
+    /// Find the largest value less than or equal to the key and return its index and a borrow of it.
+    /// The key does not need to be present in the set.
     ///
+    /// # Examples:
     /// ```
     ///     # use hitree::hiset::HiSet;
-    ///     let mut set = HiSet::<String>::new();
-    ///     assert_eq!(set.insert("first"), true);
+    ///     let set = HiSet::<i32>::from([10,20,30].into_iter());
+    ///
+    ///     assert_eq!(set.floor(&5), None);
+    ///     assert_eq!(set.floor(&20), Some((1, &20)));
+    ///     assert_eq!(set.floor(&25), Some((1, &20)));
+    ///     assert_eq!(set.floor(&35), Some((2, &30)));
+    /// ```
+    pub fn floor<KEY>(&self, key: &KEY) -> Option<(usize,&T)>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.find_closest(key, &self.comparator, true, true).map(|(index, node)| (index, &node.value) )
+    }
+
+    /// Find the smallest value greater than or equal to the key and return its index and a borrow of it.
+    /// The key does not need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from([10,20,30].into_iter());
+    ///
+    ///     assert_eq!(set.ceiling(&5), Some((0, &10)));
+    ///     assert_eq!(set.ceiling(&20), Some((1, &20)));
+    ///     assert_eq!(set.ceiling(&25), Some((2, &30)));
+    ///     assert_eq!(set.ceiling(&35), None);
+    /// ```
+    pub fn ceiling<KEY>(&self, key: &KEY) -> Option<(usize,&T)>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.find_closest(key, &self.comparator, false, true).map(|(index, node)| (index, &node.value) )
+    }
+
+    /// Find the largest value strictly less than the key and return its index and a borrow of it.
+    /// The key does not need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from([10,20,30].into_iter());
+    ///
+    ///     assert_eq!(set.prev(&10), None);
+    ///     assert_eq!(set.prev(&20), Some((0, &10)));
+    ///     assert_eq!(set.prev(&25), Some((1, &20)));
+    /// ```
+    pub fn prev<KEY>(&self, key: &KEY) -> Option<(usize,&T)>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.find_closest(key, &self.comparator, true, false).map(|(index, node)| (index, &node.value) )
+    }
+
+    /// Find the smallest value strictly greater than the key and return its index and a borrow of it.
+    /// The key does not need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let set = HiSet::<i32>::from([10,20,30].into_iter());
+    ///
+    ///     assert_eq!(set.next(&5), Some((0, &10)));
+    ///     assert_eq!(set.next(&20), Some((2, &30)));
+    ///     assert_eq!(set.next(&30), None);
+    /// ```
+    pub fn next<KEY>(&self, key: &KEY) -> Option<(usize,&T)>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.root.find_closest(key, &self.comparator, false, false).map(|(index, node)| (index, &node.value) )
+    }
+
+
+
+    /// Remove the smallest value from the set and return it.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut set = HiSet::<i32>::new();
+    ///     set.insert(10);
+    ///     set.insert(15);
+    ///     set.insert(5);
+    ///
+    ///     assert_eq!(set.len(), 3);
+    ///     assert_eq!(set.take_first(), Some(5));
+    ///     assert_eq!(set.len(), 2);
+    ///     assert_eq!(set.take_first(), Some(10));
+    ///     assert_eq!(set.len(), 1);
+    ///     assert_eq!(set.take_first(), Some(15));
+    ///     assert_eq!(set.len(), 0);
+    ///     assert_eq!(set.take_first(), None);
+    /// ```
+    ///
+    pub fn take_first(&mut self) -> Option<T> {
+        self.root.take_leftmost_node().map(|node| node.value )
+    }
+
+    /// Remove the largest value from the set and return it.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut set = HiSet::<i32>::new();
+    ///     set.insert(10);
+    ///     set.insert(15);
+    ///     set.insert(5);
+    ///
+    ///     assert_eq!(set.len(), 3);
+    ///     assert_eq!(set.take_last(), Some(15));
+    ///     assert_eq!(set.len(), 2);
+    ///     assert_eq!(set.take_last(), Some(10));
+    ///     assert_eq!(set.len(), 1);
+    ///     assert_eq!(set.take_last(), Some(5));
+    ///     assert_eq!(set.len(), 0);
+    ///     assert_eq!(set.take_last(), None);
+    /// ```
+    ///
+    pub fn take_last(&mut self) -> Option<T> {
+        self.root.take_rightmost_node().map(|node| node.value )
+    }
+
+
+    /// Take the value equal to the key out of the set and return it, or return `None` if there is no such value.
+    /// Whatever you use as key must give the same ordering results as the comparator of the set gives on `&T`!
+    ///
+    ///  # Examples:
+    ///
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut set = HiSet::<String>::new();
+    ///     assert_eq!(set.insert("first"), true);
     ///     assert_eq!(set.insert("second"), true);
     ///     assert_eq!(set.insert("third"), true);
     ///     assert_eq!(set.len(), 3);
@@ -436,14 +626,14 @@ impl <T> HiSet<T>
     ///
     /// ```
     pub fn take<KEY>(&mut self, key: &KEY) -> Option<T>
-        where KEY: ?Sized + Ord, T: Borrow<KEY>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
     {
         let key = key.borrow();
-        self.root.take_node_by_key(key).map(|node| node.value )
+        self.root.take_node_by_key(key, &self.comparator).map(|node| node.value )
     }
 
-    /// Take an entry by reference to another value and return it.
-    /// Whatever you use as key must give the same `Ord` results as `Ord` on `&T`!
+    /// Take the value at given position out of the set and return it. Values after it move one index down.
+    /// Return `None` if the index is out of bounds.
     ///
     ///  # Examples:
     ///
@@ -471,13 +661,253 @@ impl <T> HiSet<T>
     }
 
 
+    /// Split the set in two at given index. Values with index `>= index` are moved into the returned set,
+    /// the rest stays in `self`. Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut low = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let high = low.split_off_index(7);
+    ///     assert!(low.iter().eq((0..7).collect::<Vec<_>>().iter()));
+    ///     assert!(high.iter().eq([7,8,9].iter()));
+    /// ```
+    pub fn split_off_index(&mut self, index: usize) -> HiSet<T,A,C>
+        where C: Clone
+    {
+        let (low, high) = self.root.take().split_at_index(index);
+        self.root = low;
+        HiSet { root: high, comparator: self.comparator.clone() }
+    }
+
+    /// Split the set in two at given key. Values greater or equal to the key are moved into the returned set,
+    /// the rest stays in `self`. Takes O(log n).
+    /// Whatever you use as key must give the same ordering results as the comparator of the set gives on `&T`!
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut low = HiSet::<String>::from(["apple","kiwi","mango","pear"].into_iter());
+    ///     let high = low.split_off("m");
+    ///     assert!(low.iter().eq(["apple","kiwi"].iter()));
+    ///     assert!(high.iter().eq(["mango","pear"].iter()));
+    /// ```
+    pub fn split_off<KEY>(&mut self, key: &KEY) -> HiSet<T,A,C>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY> + Clone
+    {
+        self.split_off_index(self.lower_bound_index(key))
+    }
+
+    /// Move all values from `other` into `self`, leaving `other` empty.
+    ///
+    /// If all values of one set are smaller than all values of the other one, the trees are joined in O(log n).
+    /// Otherwise both sets are merged in O(n + m). Values from `other` equal to a value already in `self` are dropped,
+    /// just like with [insert](`HiSet<T>::insert`).
+    ///
+    /// Both sets must be ordered the same way, by equally configured comparators. This is only checked in debug builds,
+    /// where it takes O(m).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut a = HiSet::<i32>::from_sorted_iter(0..5).unwrap();
+    ///     let mut b = HiSet::<i32>::from_sorted_iter(5..10).unwrap();
+    ///     a.append(&mut b);   // joined in O(log n)
+    ///     assert!(b.is_empty());
+    ///     assert!(a.iter().eq((0..10).collect::<Vec<_>>().iter()));
+    ///
+    ///     let mut c = HiSet::<i32>::from([3,20].into_iter());
+    ///     a.append(&mut c);   // merged in O(n + m)
+    ///     assert!(a.iter().eq([0,1,2,3,4,5,6,7,8,9,20].iter()));
+    /// ```
+    pub fn append(&mut self, other: &mut HiSet<T,A,C>) {
+        debug_assert!(self.same_order(other), "appended set is ordered differently");
+        let other = other.root.take();
+        if other.is_empty() {
+            return
+        }
+        if self.is_empty() {
+            self.root = other;
+            return
+        }
+        let (self_first, self_last) = (&self.root.leftmost_node().unwrap().value, &self.root.rightmost_node().unwrap().value);
+        let (other_first, other_last) = (&other.leftmost_node().unwrap().value, &other.rightmost_node().unwrap().value);
+        if self.comparator.compare(self_last, other_first) == Ordering::Less {
+            self.root = Ref::join2(self.root.take(), other);
+        } else if self.comparator.compare(other_last, self_first) == Ordering::Less {
+            self.root = Ref::join2(other, self.root.take());
+        } else {
+            // ranges overlap, merge both sets into a new tree
+            let mut merged = Vec::with_capacity(self.len() + other.count);
+            let self_count = self.root.count;
+            let mut left = HiSetOwnedIterator::new(self.root.take(), 0, self_count).peekable();
+            let other_count = other.count;
+            let mut right = HiSetOwnedIterator::new(other, 0, other_count).peekable();
+            loop {
+                let from_left = match (left.peek(), right.peek()) {
+                    (Some(l), Some(r)) => match self.comparator.compare(l, r) {
+                        Ordering::Less => true,
+                        Ordering::Greater => false,
+                        Ordering::Equal => {
+                            right.next();   // keep the value already in self
+                            true
+                        }
+                    },
+                    (Some(_), None) => true,
+                    (None, Some(_)) => false,
+                    (None, None) => break,
+                };
+                merged.push(if from_left { left.next() } else { right.next() }.unwrap());
+            }
+            self.root = Ref::from_sorted_vec(merged);
+        }
+    }
+
+    /// Remove all values in given index range and return them as a new set.
+    /// The range is cut out of the tree with a few splits and joins, so it takes O(log n) regardless of its size.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let removed = s.remove_range_by_index(..3);
+    ///     assert!(removed.iter().eq([0,1,2].iter()));
+    ///     assert!(s.iter().eq([3,4,5,6,7,8,9].iter()));
+    /// ```
+    pub fn remove_range_by_index(&mut self, range: impl std::ops::RangeBounds<usize>) -> HiSet<T,A,C>
+        where C: Clone
+    {
+        let (start, end) = index_bounds(range, self.root.count);
+        HiSet { root: self.root.take_range(start, end), comparator: self.comparator.clone() }
+    }
+
+    /// Remove all values falling within given key bounds and return them as a new set. Takes O(log n).
+    /// Bounds don't need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from([0,10,20,30,40,50].into_iter());
+    ///     let removed = s.remove_range(15..=40);
+    ///     assert!(removed.iter().eq([20,30,40].iter()));
+    ///     assert!(s.iter().eq([0,10,50].iter()));
+    /// ```
+    pub fn remove_range<KEY,R>(&mut self, range: R) -> HiSet<T,A,C>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY> + Clone, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
+        HiSet { root: self.root.take_range(start, end), comparator: self.comparator.clone() }
+    }
+
+    /// Remove all values falling within given key bounds and return double ended iterator over them.
+    /// Removing takes O(log n), iterating over the k removed values O(k).
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut log = HiSet::<(u64,String)>::new();
+    ///     log.insert((100, "start".to_string()));
+    ///     log.insert((200, "running".to_string()));
+    ///     log.insert((300, "done".to_string()));
+    ///     // trim everything older than timestamp 250
+    ///     let old: Vec<_> = log.drain_range(..(250, String::new())).map(|(time, _)| time ).collect();
+    ///     assert_eq!(old, vec![100, 200]);
+    ///     assert_eq!(log.len(), 1);
+    /// ```
+    pub fn drain_range<KEY,R>(&mut self, range: R) -> HiSetOwnedIterator<T,A>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
+        HiSetOwnedIterator::new(self.root.take_range(start, end), 0, end - start)
+    }
+
+    /// Keep only the values for which `keep` returns true.
+    /// The tree is rebuilt from the kept values in a single O(n) pass.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     s.retain(|value| value % 3 == 0);
+    ///     assert!(s.iter().eq([0,3,6,9].iter()));
+    /// ```
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        self.split_by_index(|_, value| keep(value));
+    }
+
+    /// Keep only the values for which `keep` returns true, given their index and a reference to them.
+    /// The index is the index of the value before any values were removed.
+    /// The tree is rebuilt from the kept values in a single O(n) pass.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<char>::from(['a','b','c','d','e'].into_iter());
+    ///     s.retain_by_index(|index, _| index % 2 == 0);
+    ///     assert!(s.iter().eq(['a','c','e'].iter()));
+    /// ```
+    pub fn retain_by_index(&mut self, keep: impl FnMut(usize, &T) -> bool) {
+        self.split_by_index(keep);
+    }
+
+    /// Return iterator removing the values for which `extract` returns true and yielding them in order.
+    ///
+    /// Like the `extract_if` of standard collections, values are only visited and removed as the iterator is advanced,
+    /// so values after the point where it is dropped stay in the set. The tree is rebuilt from all the values left
+    /// in a single O(n) pass when the iterator is dropped, also if `extract` panics.
+    /// If the iterator is leaked, for example with `mem::forget`, the values are leaked with it and the set is left empty.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let odd: Vec<i32> = s.extract_if(|value| value % 2 == 1).collect();
+    ///     assert_eq!(odd, vec![1,3,5,7,9]);
+    ///     assert!(s.iter().eq([0,2,4,6,8].iter()));
+    ///
+    ///     assert_eq!(s.extract_if(|value| *value > 2).next(), Some(4));
+    ///     assert!(s.iter().eq([0,2,6,8].iter()));
+    /// ```
+    pub fn extract_if<F>(&mut self, extract: F) -> HiSetExtractIf<'_,T,F,A>
+        where F: FnMut(&T) -> bool
+    {
+        let capacity = self.root.count;
+        HiSetExtractIf { rebuild: Rebuild::new(&mut self.root, capacity), extract }
+    }
+
+    /// Keep the values for which `keep` returns true and drop the others. The tree is rebuilt in O(n).
+    /// If `keep` panics, the set keeps the values kept so far and the ones not visited yet.
+    fn split_by_index(&mut self, mut keep: impl FnMut(usize, &T) -> bool) {
+        let capacity = self.root.count;
+        let mut kept = Rebuild::new(&mut self.root, capacity);
+        let mut index = 0;
+        while let Some(value) = kept.rest.peek() {
+            if keep(index, value) {
+                kept.values.push(kept.rest.next().unwrap());
+            } else {
+                kept.rest.next();
+            }
+            index += 1;
+        }
+    }
 
 
     /// Return iterator over all `&T`.
     ///
+    /// The iterator knows its exact length and skips over whole subtrees in `nth`,
+    /// so paging through the set with `skip` and `take` costs O(log n) plus the size of the page.
     ///
-    pub fn iter(&self) -> HiSetIterator<'_,T> {
-        HiSetIterator { set: self, start: 0, end: self.root.count }
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from_sorted_iter(0..1000).unwrap();
+    ///     let (page, page_size) = (7, 20);
+    ///     let mut i = s.iter().skip(page * page_size).take(page_size);
+    ///     assert_eq!(i.len(), 20);
+    ///     assert_eq!(i.next(), Some(&140));
+    /// ```
+    pub fn iter(&self) -> HiSetIterator<'_,T,A> {
+        HiSetIterator::new(&self.root, 0, self.root.count)
     }
 
 
@@ -490,85 +920,584 @@ impl <T> HiSet<T>
     ///     let mut r = s.range_by_index(2..=5).map(|v| *v);
     ///     assert!(r.eq( [2,3,4,5].into_iter() ));
     /// ```
-    pub fn range_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> HiSetIterator<'_,T> {
-        use std::ops::Bound::*;
-        let start = match range.start_bound() {
-            Included(index) => *index,
-            Excluded(index) => *index + 1,
-            Unbounded => 0
-        };
-        let end = match range.end_bound() {
-            Included(index) => *index + 1,
-            Excluded(index) => *index,
-            Unbounded => self.root.count
-        };
+    pub fn range_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> HiSetIterator<'_,T,A> {
+        let (start, end) = index_bounds(range, self.root.count);
 
-        HiSetIterator { set: self, start, end }
+        HiSetIterator::new(&self.root, start, end)
     }
 
-    /// Return double ended iterator over `&mut T` in given index range.
+
+    /// Consume the set and return double ended iterator over owned values in given index range.
+    /// Values outside of the range are dropped, whole subtrees at once where possible.
     ///
     /// # Examples:
     /// ```
     ///   #  use hitree::hiset::HiSet;
-    ///     let mut s = HiSet::<i32>::from([0,1,2,3,4,5,6].into_iter());
-    ///     let mut r = s.range_by_index_mut(2..=5).map(|v| *v);
-    ///     assert!(r.eq( [2,3,4,5].into_iter() ));
+    ///     let s = HiSet::<String>::from(["a","b","c","d","e"].into_iter());
+    ///     let top: Vec<String> = s.into_range_by_index(3..).rev().collect();
+    ///     assert_eq!(top, vec!["e".to_string(), "d".to_string()]);
     /// ```
-    pub fn range_by_index_mut(&mut self, range: impl std::ops::RangeBounds<usize>) -> HiSetIteratorMut<'_,T> {
+    pub fn into_range_by_index(self, range: impl std::ops::RangeBounds<usize>) -> HiSetOwnedIterator<T,A> {
+        let (start, end) = index_bounds(range, self.root.count);
+
+        HiSetOwnedIterator::new(self.root, start, end)
+    }
+
+
+    /// Return range of indexes of values falling within given key bounds.
+    /// Bounds don't need to be present in the set.
+    /// If the start bound is above the end bound, returned range is empty.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from([0,10,20,30,40,50].into_iter());
+    ///     assert_eq!(s.index_range(15..=40), 2..5);
+    ///     assert_eq!(s.index_range(20..40), 2..4);
+    ///     assert_eq!(s.index_range(..25), 0..3);
+    ///     assert_eq!(s.index_range(45..), 5..6);
+    ///     assert_eq!(s.index_range(60..), 6..6);
+    /// ```
+    pub fn index_range<KEY,R>(&self, range: R) -> std::ops::Range<usize>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
         use std::ops::Bound::*;
         let start = match range.start_bound() {
-            Included(index) => *index,
-            Excluded(index) => *index + 1,
+            Included(key) => self.lower_bound_index(key),
+            Excluded(key) => self.upper_bound_index(key),
             Unbounded => 0
         };
         let end = match range.end_bound() {
-            Included(index) => *index + 1,
-            Excluded(index) => *index,
+            Included(key) => self.upper_bound_index(key),
+            Excluded(key) => self.lower_bound_index(key),
             Unbounded => self.root.count
         };
-
-        HiSetIteratorMut { set: self, start, end }
+        start..end.max(start)
     }
 
+    /// Return double ended iterator over `&T` with values falling within given key bounds.
+    /// Bounds don't need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     use std::ops::Bound::*;
+    ///     let s = HiSet::<i32>::from([0,10,20,30,40,50].into_iter());
+    ///     assert!(s.range(15..=40).eq([20,30,40].iter()));
+    ///     assert!(s.range((Excluded(20),Unbounded)).rev().eq([50,40,30].iter()));
+    ///
+    ///     let s = HiSet::<String>::from(["apple","banana","cherry"].into_iter());
+    ///     assert!(s.range::<str,_>((Included("b"),Excluded("c"))).eq(["banana"].iter()));
+    /// ```
+    pub fn range<KEY,R>(&self, range: R) -> HiSetIterator<'_,T,A>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
 
+        HiSetIterator::new(&self.root, start, end)
+    }
 
-}
-
-#[test]
-fn test_hiset_range() {
-        let s = HiSet::<i32>::from([0,1,2,3,4,5,6].into_iter() );
-        let r = s.range_by_index(2..=5).map(|v| *v);
-        assert!(r.eq( [2,3,4,5].into_iter() ));
-}
-
-pub struct HiSetOwnedIterator<T>
-    where T: Ord
-{
-    root: Ref<T>,
-}
+    /// Return iterator over all `(index, &T)` pairs.
+    /// Same as `iter().enumerate()`, provided for symmetry with the range variants.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<char>::from(['c','a','b'].into_iter());
+    ///     assert!(s.iter_indexed().eq([(0,&'a'),(1,&'b'),(2,&'c')].into_iter()));
+    /// ```
+    pub fn iter_indexed(&self) -> HiSetIndexedIterator<'_,T,A> {
+        HiSetIndexedIterator { inner: self.iter() }
+    }
 
-impl <T> Iterator for HiSetOwnedIterator<T>
-    where T: Ord
-{
-    type Item = T;
+    /// Return double ended iterator over `(index, &T)` pairs in given index range.
+    /// Unlike with `enumerate()`, the index is the index of the value in the whole set.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let mut r = s.range_by_index_indexed(5..8);
+    ///     assert_eq!(r.next(), Some((5,&5)));
+    ///     assert_eq!(r.next_back(), Some((7,&7)));
+    ///     assert_eq!(r.next(), Some((6,&6)));
+    ///     assert_eq!(r.next(), None);
+    /// ```
+    pub fn range_by_index_indexed(&self, range: impl std::ops::RangeBounds<usize>) -> HiSetIndexedIterator<'_,T,A> {
+        HiSetIndexedIterator { inner: self.range_by_index(range) }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // take leftmost node without bothering to re-balance or maintain node counts
-        self.root.consume_next()
+    /// Return double ended iterator over `(index, &T)` pairs with values falling within given key bounds.
+    /// The index is the index of the value in the whole set.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from([0,10,20,30,40,50].into_iter());
+    ///     assert!(s.range_indexed(15..=40).eq([(2,&20),(3,&30),(4,&40)].into_iter()));
+    ///     assert_eq!(s.range_indexed(25..).rev().next(), Some((5,&50)));
+    /// ```
+    pub fn range_indexed<KEY,R>(&self, range: R) -> HiSetIndexedIterator<'_,T,A>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        HiSetIndexedIterator { inner: self.range(range) }
     }
-}
 
-impl <T> IntoIterator for HiSet<T>
-    where T: Ord
-{
-    type Item = T;
-    type IntoIter = HiSetOwnedIterator<T>;
 
-    /// Turn `HiSet<T>` into an `Iterator` of owned `T`
+    /// Return a cursor pointing at the value with given index, or at the ghost position between the last and
+    /// the first value if the index is out of bounds.
+    ///
+    /// # Examples:
     /// ```
-    ///  # use hitree::hiset::HiSet;
-    /// let mut s = HiSet::<String>::new();
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let mut c = s.cursor_at(3);
+    ///     assert_eq!(c.current(), Some(&3));
+    ///     c.move_next();
+    ///     assert_eq!((c.index(), c.current()), (Some(4), Some(&4)));
+    ///     c.move_prev();
+    ///     c.move_prev();
+    ///     assert_eq!((c.index(), c.current()), (Some(2), Some(&2)));
+    /// ```
+    pub fn cursor_at(&self, index: usize) -> Cursor<'_,T,A,C> {
+        let mut cursor = Cursor { set: self, path: Vec::new(), index: 0 };
+        cursor.seek_index(index);
+        cursor
+    }
+
+    /// Return a cursor pointing at the value equal to the key, or the smallest greater value if the key is not
+    /// in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from([10,20,30].into_iter());
+    ///     let c = s.cursor_at_key(&15);
+    ///     assert_eq!((c.index(), c.current()), (Some(1), Some(&20)));
+    /// ```
+    pub fn cursor_at_key<KEY>(&self, key: &KEY) -> Cursor<'_,T,A,C>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        self.cursor_at(self.lower_bound_index(key))
+    }
+
+    /// Return a cursor allowing modification of the set, pointing at the value with given index,
+    /// or at the ghost position between the last and the first value if the index is out of bounds.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from_sorted_iter(0..10).unwrap();
+    ///     let mut c = s.cursor_mut_at(2);
+    ///     // remove every other value
+    ///     while c.current().is_some() {
+    ///         c.remove_current();
+    ///         c.move_next();
+    ///     }
+    ///     assert!(s.iter().eq([0,1,3,5,7,9].iter()));
+    /// ```
+    pub fn cursor_mut_at(&mut self, index: usize) -> CursorMut<'_,T,A,C> {
+        let index = index.min(self.len());
+        CursorMut { set: self, index }
+    }
+
+    /// Return a cursor allowing modification of the set, pointing at the value equal to the key,
+    /// or the smallest greater value if the key is not in the set.
+    pub fn cursor_mut_at_key<KEY>(&mut self, key: &KEY) -> CursorMut<'_,T,A,C>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let index = self.lower_bound_index(key);
+        CursorMut { set: self, index }
+    }
+
+
+
+}
+
+#[test]
+fn test_hiset_range() {
+        let s = HiSet::<i32>::from([0,1,2,3,4,5,6].into_iter() );
+        let r = s.range_by_index(2..=5).copied();
+        assert!(r.eq( [2,3,4,5].into_iter() ));
+        // bounds at usize::MAX are clamped like any other
+        use std::ops::Bound::*;
+        assert!(s.range_by_index(3..=usize::MAX).copied().eq(3..7));
+        assert_eq!(s.range_by_index((Excluded(usize::MAX), Unbounded)).len(), 0);
+}
+
+#[test]
+fn test_hiset_take_last() {
+        let mut s = HiSet::<i32>::from(0..20);
+        for i in (0..20).rev() {
+            assert_eq!(s.take_last(), Some(i));
+        }
+        assert_eq!(s.take_last(), None);
+}
+
+#[test]
+fn test_hiset_key_range() {
+        let s = HiSet::<i32>::from((0..100).map(|i| i * 2));
+        for low in -2..202 {
+            for high in low-2..202 {
+                assert!(s.range(low..high).copied().eq((0..100).map(|i| i * 2).filter(|v| *v >= low && *v < high)));
+                assert!(s.range(low..=high).copied().eq((0..100).map(|i| i * 2).filter(|v| *v >= low && *v <= high)));
+            }
+        }
+}
+
+#[test]
+fn test_hiset_neighbours() {
+        let s = HiSet::<i32>::from((0..50).map(|i| i * 2));
+        for key in -1..101 {
+            let below = (0..50).map(|i| i * 2).enumerate().rev().find(|(_, v)| *v < key);
+            let above = (0..50).map(|i| i * 2).enumerate().find(|(_, v)| *v > key);
+            let equal = (0..50).map(|i| i * 2).enumerate().find(|(_, v)| *v == key);
+            assert_eq!(s.prev(&key).map(|(i, v)| (i, *v)), below);
+            assert_eq!(s.next(&key).map(|(i, v)| (i, *v)), above);
+            assert_eq!(s.floor(&key).map(|(i, v)| (i, *v)), equal.or(below));
+            assert_eq!(s.ceiling(&key).map(|(i, v)| (i, *v)), equal.or(above));
+        }
+}
+
+#[test]
+fn test_hiset_from_sorted_iter() {
+        for count in 0..70 {
+            let mut s = HiSet::<i32>::from_sorted_iter(0..count).unwrap();
+            assert_eq!(s.len(), count as usize);
+            assert!(s.iter().copied().eq(0..count));
+            // the tree must stay usable for further modifications
+            s.insert(-1);
+            s.insert(count);
+            assert_eq!(s.take_by_index(1), if count > 0 { Some(0) } else { Some(count) });
+            assert_eq!(s.index_of(&-1), Some(0));
+        }
+}
+
+#[test]
+fn test_hiset_comparator() {
+        let reverse = |a: &i32, b: &i32| b.cmp(a);
+        let mut s = HiSet::with_comparator(reverse);
+        for value in (0..50).map(|i| (i * 17) % 50) {
+            assert!(s.insert(value));
+        }
+        assert!(!s.insert(10));
+        assert!(s.iter().copied().eq((0..50).rev()));
+        assert_eq!(s.index_of(&49), Some(0));
+        assert_eq!(s.lower_bound_index(&10), 39);
+        assert_eq!(s.floor(&-5), Some((49, &0)));
+        assert_eq!(s.take(&20), Some(20));
+        assert!(!s.contains(&20));
+        let mut high = s.split_off(&25);
+        assert!(s.iter().copied().eq((26..50).rev()));
+        assert!(high.iter().copied().eq((0..=25).rev().filter(|&value| value != 20)));
+        let other: HiSet<i32,(),_> = [30,20,10].into_iter().fold(HiSet::with_comparator(reverse), |mut set, value| { set.insert(value); set });
+        assert!(high.union(&other).copied().eq((0..=30).rev().filter(|&value| value <= 25 || value == 30)));
+        assert!((&high & &other).iter().eq([10].iter()));
+        s.append(&mut high);
+        assert!(s.iter().copied().eq((0..50).rev().filter(|&value| value != 20)));
+
+        #[derive(Clone, Default)]
+        struct CaseInsensitive;
+        impl Comparator<str> for CaseInsensitive {
+            fn compare(&self, a: &str, b: &str) -> Ordering {
+                a.bytes().map(|c| c.to_ascii_lowercase()).cmp(b.bytes().map(|c| c.to_ascii_lowercase()))
+            }
+        }
+        impl Comparator<String> for CaseInsensitive {
+            fn compare(&self, a: &String, b: &String) -> Ordering {
+                Comparator::<str>::compare(self, a, b)
+            }
+        }
+        let words: HiSet<String,(),CaseInsensitive> = HiSet::from(["pear","Apple","apple","KIWI","banana"].into_iter());
+        assert!(words.iter().eq(["Apple","banana","KIWI","pear"].iter()));
+        assert_eq!(words.get("kiwi"), Some(&"KIWI".to_string()));
+        assert_eq!(words.index_of("APPLE"), Some(0));
+        assert!(words.range("B".to_string().."L".to_string()).eq(["banana","KIWI"].iter()));
+}
+
+#[test]
+fn test_hiset_mismatched_comparators() {
+        // both sets have the same type, but their comparators can be configured differently
+        fn order(descending: bool) -> impl Fn(&i32, &i32) -> Ordering + Clone {
+            move |a, b| if descending { b.cmp(a) } else { a.cmp(b) }
+        }
+        let set = |descending: bool, values: &[i32]| {
+            let mut set = HiSet::with_comparator(order(descending));
+            set.extend(values.iter().copied());
+            set
+        };
+        // equally configured comparators holding state take the usual paths
+        let mut a = set(true, &[4,5]);
+        a.append(&mut set(true, &[1,2,3]));
+        assert!(a.iter().eq([5,4,3,2,1].iter()));
+        a.union_with(&set(true, &[0,6]));
+        a.intersect_with(&set(true, &[0,1,2,5,6,7]));
+        assert!(a.iter().eq([6,5,2,1,0].iter()));
+        let b = set(true, &[3,2]);
+        assert!((&a ^ &b).iter().eq([6,5,3,1,0].iter()));
+        assert!(a.union(&b).eq([6,5,3,2,1,0].iter()));
+        // sets ordered differently are caught in debug builds
+        if cfg!(debug_assertions) {
+            let other = set(false, &[1,2,3]);
+            let fails = |operation: &dyn Fn(&mut HiSet<i32,(),_>)| {
+                let mut a = set(true, &[1,3,5]);
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| operation(&mut a) )).is_err()
+            };
+            assert!(fails(&|a| a.append(&mut other.clone()) ));
+            assert!(fails(&|a| a.union_with(&other) ));
+            assert!(fails(&|a| a.intersect_with(&other) ));
+            assert!(fails(&|a| { a.union(&other).count(); } ));
+            assert!(fails(&|a| { let _ = &*a | &other; } ));
+            assert!(fails(&|a| { a.is_disjoint(&other); } ));
+        }
+}
+
+#[test]
+fn test_hiset_iterators() {
+        let mut s = HiSet::<i32>::from_sorted_iter(0..40).unwrap();
+        for start in 0..41 {
+            for end in start..42 {
+                let expected = start..end.min(40);
+                assert!(s.range_by_index(start as usize..end as usize).copied().eq(expected.clone()));
+                assert!(s.range_by_index(start as usize..end as usize).rev().copied().eq(expected.clone().rev()));
+                assert!(s.range_by_index_mut(start as usize..end as usize).map(|v| *v).eq(expected.clone()));
+                // alternate between the ends
+                let mut i = s.range_by_index(start as usize..end as usize);
+                let mut e = expected.clone();
+                loop {
+                    let (a, b) = (i.next().copied(), e.next());
+                    assert_eq!(a, b);
+                    let (c, d) = (i.next_back().copied(), e.next_back());
+                    assert_eq!(c, d);
+                    if b.is_none() && d.is_none() {
+                        break
+                    }
+                }
+            }
+        }
+}
+
+#[test]
+fn test_hiset_iter_mut_mixed_ends() {
+        // Borrows returned from both ends must never alias: hold all of them at once, then touch each one.
+        for len in 0..20 {
+            let mut s = HiSet::<(i32,usize)>::from_sorted_iter((0..len).map(|i| (i, 0))).unwrap();
+            for start in 0..len as usize {
+                for end in start..len as usize {
+                    let mut i = s.range_by_index_mut(start..=end);
+                    let mut borrowed = Vec::new();
+                    let mut front = true;
+                    while let Some(value) = if front { i.next() } else { i.next_back() } {
+                        borrowed.push(value);
+                        front = !front;
+                    }
+                    assert_eq!(borrowed.len(), end - start + 1);
+                    for value in borrowed {
+                        value.1 += 1;
+                    }
+                }
+            }
+            // every value was returned once for each range containing it
+            let n = len as usize;
+            assert!(s.iter().enumerate().all(|(index, value)| value.1 == (index + 1) * (n - index)));
+        }
+}
+
+#[test]
+fn test_hiset_iterator_nth() {
+        let mut s = HiSet::<i32>::from_sorted_iter(0..50).unwrap();
+        for start in 0..50 {
+            for n in 0..52 {
+                let mut i = s.range_by_index(start..);
+                assert_eq!(i.nth(n).copied(), (start as i32..50).nth(n));
+                assert_eq!(i.len(), (50 - start).saturating_sub(n + 1));
+                assert_eq!(i.next().copied(), (start as i32..50).nth(n + 1));
+                let mut i = s.range_by_index_mut(..start);
+                assert_eq!(i.nth_back(n).map(|v| *v), (0..start as i32).nth_back(n));
+                assert_eq!(i.len(), start.saturating_sub(n + 1));
+            }
+        }
+        assert_eq!(s.iter().skip(10).take(5).collect::<Vec<_>>(), vec![&10, &11, &12, &13, &14]);
+        assert_eq!(s.iter().rev().skip(10).size_hint(), (40, Some(40)));
+}
+
+#[test]
+fn test_hiset_indexed_iterator() {
+        let s = HiSet::<i32>::from_sorted_iter((0..30).map(|i| i * 3)).unwrap();
+        for start in 0..31 {
+            for end in start..31 {
+                assert!(s.range_by_index_indexed(start..end).eq(s.iter().enumerate().skip(start).take(end - start)));
+                assert!(s.range_by_index_indexed(start..end).rev().eq(s.iter().enumerate().skip(start).take(end - start).rev()));
+            }
+        }
+        let mut i = s.iter_indexed();
+        assert_eq!(i.nth(10), Some((10, &30)));
+        assert_eq!(i.nth_back(10), Some((19, &57)));
+        assert_eq!(i.len(), 8);
+        assert_eq!(i.nth(usize::MAX), None);
+        assert_eq!(i.next(), None);
+}
+
+#[test]
+fn test_hiset_owned_iterator() {
+        for start in 0..22 {
+            for end in start..22 {
+                let s = HiSet::<String>::from((0..20).map(|i| format!("{:02}", i)));
+                let expected: Vec<String> = (start.min(20)..end.min(20)).map(|i| format!("{:02}", i)).collect();
+                let mut i = s.into_range_by_index(start..end);
+                assert_eq!(i.len(), expected.len());
+                let mut e = expected.into_iter();
+                loop {
+                    let (a, b) = (i.next(), e.next());
+                    assert_eq!(a, b);
+                    let (c, d) = (i.next_back(), e.next_back());
+                    assert_eq!(c, d);
+                    if b.is_none() && d.is_none() {
+                        break
+                    }
+                }
+            }
+        }
+}
+
+/// Iterator over owned `T`, consuming the set.
+/// Returned by [into_iter](`HiSet<T>::into_iter`) and [into_range_by_index](`HiSet<T>::into_range_by_index`).
+pub struct HiSetOwnedIterator<T,A = ()>
+    where A: Augment<T>
+{
+    pending: VecDeque<PendingOwned<T,A>>,
+    start:  usize,
+    end:    usize,
+}
+
+/// Part of the tree not visited yet by `HiSetOwnedIterator`, in order: either a single owned value or a whole subtree.
+enum PendingOwned<T,A = ()>
+    where A: Augment<T>
+{
+    Value(T),
+    Subtree(Ref<T,A>),
+}
+
+impl <T,A> HiSetOwnedIterator<T,A>
+    where A: Augment<T>
+{
+    /// Create iterator over values with index in `start..end` within subtree `root`, dropping all others.
+    fn new(root: Ref<T,A>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.into_pending_range(start, end, &mut pending);
+        }
+        HiSetOwnedIterator { pending, start, end }
+    }
+}
+
+impl <T,A> Iterator for HiSetOwnedIterator<T,A>
+    where A: Augment<T>
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Skipped subtrees are dropped whole without visiting them in order.
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                PendingOwned::Value(value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                PendingOwned::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // drop the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree, continue with its left part
+                        let Node { value, left, right, .. } = *subtree.node.unwrap();    // only non-empty subtrees are pending
+                        if !right.is_empty() {
+                            self.pending.push_front(PendingOwned::Subtree(right));
+                        }
+                        self.pending.push_front(PendingOwned::Value(value));
+                        if !left.is_empty() {
+                            self.pending.push_front(PendingOwned::Subtree(left));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <T,A> DoubleEndedIterator for HiSetOwnedIterator<T,A>
+    where A: Augment<T>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Skipped subtrees are dropped whole.
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                PendingOwned::Value(value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                PendingOwned::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // drop the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree, continue with its right part
+                        let Node { value, left, right, .. } = *subtree.node.unwrap();    // only non-empty subtrees are pending
+                        if !left.is_empty() {
+                            self.pending.push_back(PendingOwned::Subtree(left));
+                        }
+                        self.pending.push_back(PendingOwned::Value(value));
+                        if !right.is_empty() {
+                            self.pending.push_back(PendingOwned::Subtree(right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <T,A> ExactSizeIterator for HiSetOwnedIterator<T,A>
+    where A: Augment<T>
+{}
+
+impl <T,A> FusedIterator for HiSetOwnedIterator<T,A>
+    where A: Augment<T>
+{}
+
+impl <T,A,C> IntoIterator for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = T;
+    type IntoIter = HiSetOwnedIterator<T,A>;
+
+    /// Turn `HiSet<T>` into an `Iterator` of owned `T`
+    /// ```
+    ///  # use hitree::hiset::HiSet;
+    /// let mut s = HiSet::<String>::new();
     /// s.insert("This");
     /// s.insert("is");
     /// s.insert("a");
@@ -582,10 +1511,79 @@ impl <T> IntoIterator for HiSet<T>
     /// assert_eq!(i.next(), None);
     /// ```
     fn into_iter(self) -> Self::IntoIter {
-        HiSetOwnedIterator { root: self.root }
+        let end = self.root.count;
+        HiSetOwnedIterator::new(self.root, 0, end)
+    }
+}
+
+/// Values of a tree being rebuilt: `values` already moved out in order, followed by `rest` not visited yet.
+/// The tree is rebuilt from both when the guard is dropped, even while unwinding from a panic in a comparator
+/// or a user closure, so that the tree is never left without the values not visited yet.
+struct Rebuild<'set,T,A>
+    where A: Augment<T>
+{
+    root: &'set mut Ref<T,A>,
+    values: Vec<T>,
+    rest: std::iter::Peekable<HiSetOwnedIterator<T,A>>,
+}
+
+impl <'set,T,A> Rebuild<'set,T,A>
+    where A: Augment<T>
+{
+    /// Move all values of the tree into `rest`, leaving the tree empty until the guard is dropped.
+    fn new(root: &'set mut Ref<T,A>, capacity: usize) -> Self {
+        let count = root.count;
+        let rest = HiSetOwnedIterator::new(root.take(), 0, count).peekable();
+        Rebuild { root, values: Vec::with_capacity(capacity), rest }
+    }
+}
+
+impl <'set,T,A> Drop for Rebuild<'set,T,A>
+    where A: Augment<T>
+{
+    fn drop(&mut self) {
+        let mut values = std::mem::take(&mut self.values);
+        values.extend(&mut self.rest);
+        *self.root = Ref::from_sorted_vec(values);
+    }
+}
+
+
+/// Iterator removing values matching a predicate from a `HiSet`, returned by [extract_if](`HiSet<T>::extract_if`).
+/// Values are moved out of the tree when it is created, and the set is rebuilt from the values kept and the ones
+/// not visited yet when it is dropped.
+pub struct HiSetExtractIf<'set,T,F,A = ()>
+    where A: Augment<T>
+{
+    rebuild: Rebuild<'set,T,A>,
+    extract: F,
+}
+
+impl <'set,T,F,A> Iterator for HiSetExtractIf<'set,T,F,A>
+    where A: Augment<T>, F: FnMut(&T) -> bool
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // values are moved only after `extract` returns, so none are lost if it panics
+        while let Some(value) = self.rebuild.rest.peek() {
+            if (self.extract)(value) {
+                return self.rebuild.rest.next()
+            }
+            self.rebuild.values.push(self.rebuild.rest.next().unwrap());
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.rebuild.rest.size_hint().1)
     }
 }
 
+impl <'set,T,F,A> FusedIterator for HiSetExtractIf<'set,T,F,A>
+    where A: Augment<T>, F: FnMut(&T) -> bool
+{}
+
 
 
 /// Get iterator over `&T`
@@ -609,206 +1607,2109 @@ impl <T> IntoIterator for HiSet<T>
 /// assert_eq!(i.next(), None);
 ///
 /// ```
-pub struct HiSetIterator<'set,T>
-    where T: Ord
+pub struct HiSetIterator<'set,T,A = ()>
+    where A: Augment<T>
 {
-    set:    &'set HiSet<T>,
+    pending: VecDeque<Pending<'set,T,A>>,
     start:  usize,
     end:    usize,
 }
 
-impl <'set,T> Iterator for HiSetIterator<'set,T>
-    where T: Ord
+/// Part of the tree not visited yet by `HiSetIterator`, in order: either a single value or a whole subtree.
+enum Pending<'set,T,A = ()>
+    where A: Augment<T>
+{
+    Value(&'set T),
+    Subtree(&'set Ref<T,A>),
+}
+
+impl <'set,T,A> HiSetIterator<'set,T,A>
+    where A: Augment<T>
+{
+    /// Create iterator over values with index in `start..end` within subtree pointed to by `root`.
+    fn new(root: &'set Ref<T,A>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.pending_range(start, end, &mut pending);
+        }
+        HiSetIterator { pending, start, end }
+    }
+}
+
+impl <'set,T,A> Iterator for HiSetIterator<'set,T,A>
+    where A: Augment<T>
 {
     type Item = &'set T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.start >= self.end {
-            None
-        } else {
-            let index_to_return = self.start;
-            self.start += 1;
-            self.set.get_by_index(index_to_return)
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                Pending::Value(value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                Pending::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree, continue with its left part
+                        let node = subtree.node().unwrap();    // only non-empty subtrees are pending
+                        if !node.right.is_empty() {
+                            self.pending.push_front(Pending::Subtree(&node.right));
+                        }
+                        self.pending.push_front(Pending::Value(&node.value));
+                        if !node.left.is_empty() {
+                            self.pending.push_front(Pending::Subtree(&node.left));
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
-impl <'set,T> DoubleEndedIterator for HiSetIterator<'set,T>
-    where T: Ord
+impl <'set,T,A> DoubleEndedIterator for HiSetIterator<'set,T,A>
+    where A: Augment<T>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if self.start >= self.end {
-            None
-        } else {
-            self.end -= 1;
-            self.set.get_by_index(self.end)
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                Pending::Value(value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                Pending::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree, continue with its right part
+                        let node = subtree.node().unwrap();    // only non-empty subtrees are pending
+                        if !node.left.is_empty() {
+                            self.pending.push_back(Pending::Subtree(&node.left));
+                        }
+                        self.pending.push_back(Pending::Value(&node.value));
+                        if !node.right.is_empty() {
+                            self.pending.push_back(Pending::Subtree(&node.right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'set,T,A> ExactSizeIterator for HiSetIterator<'set,T,A>
+    where A: Augment<T>
+{}
+
+impl <'set,T,A> FusedIterator for HiSetIterator<'set,T,A>
+    where A: Augment<T>
+{}
+
+
+
+
+/// Iterator over `(index, &T)` pairs, where index is the index of the value in the whole set.
+/// Returned by [iter_indexed](`HiSet<T>::iter_indexed`), [range_by_index_indexed](`HiSet<T>::range_by_index_indexed`)
+/// and [range_indexed](`HiSet<T>::range_indexed`).
+pub struct HiSetIndexedIterator<'set,T,A = ()>
+    where A: Augment<T>
+{
+    inner: HiSetIterator<'set,T,A>,
+}
+
+impl <'set,T,A> Iterator for HiSetIndexedIterator<'set,T,A>
+    where A: Augment<T>
+{
+    type Item = (usize, &'set T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let value = self.inner.nth(n)?;
+        Some((self.inner.start - 1, value))   // start now points past the returned value
+    }
+}
+
+impl <'set,T,A> DoubleEndedIterator for HiSetIndexedIterator<'set,T,A>
+    where A: Augment<T>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let value = self.inner.nth_back(n)?;
+        Some((self.inner.end, value))   // end now points at the returned value
+    }
+}
+
+impl <'set,T,A> ExactSizeIterator for HiSetIndexedIterator<'set,T,A>
+    where A: Augment<T>
+{}
+
+impl <'set,T,A> FusedIterator for HiSetIndexedIterator<'set,T,A>
+    where A: Augment<T>
+{}
+
+
+impl <'set,T,A,C> IntoIterator for &'set HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'set T;
+    type IntoIter = HiSetIterator<'set,T,A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+/// Iterator over `&mut T` in an index range.
+///
+/// The part of the tree not visited yet is kept as a sequence of disjoint parts - single values and whole subtrees,
+/// each borrowed mutably on its own. Iterating from either end only ever splits a subtree into its left subtree,
+/// value and right subtree, so no two returned borrows can point to the same value.
+pub struct HiSetIteratorMut<'set,T>
+{
+    pending: VecDeque<PendingMut<'set,T>>,
+    start:  usize,
+    end:    usize,
+}
+
+/// Part of the tree not visited yet by `HiSetIteratorMut`, in order: either a single value or a whole subtree.
+enum PendingMut<'set,T>
+{
+    Value(&'set mut T),
+    Subtree(&'set mut Ref<T>),
+}
+
+impl <'set,T> HiSetIteratorMut<'set,T>
+{
+    /// Create iterator over values with index in `start..end` within subtree pointed to by `root`.
+    fn new(root: &'set mut Ref<T>, start: usize, end: usize) -> Self {
+        let mut pending = VecDeque::new();
+        if start < end {
+            root.pending_range_mut(start, end, &mut pending);
+        }
+        HiSetIteratorMut { pending, start, end }
+    }
+}
+
+impl <'set,T> Iterator for HiSetIteratorMut<'set,T>
+{
+    type Item = &'set mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+
+    /// Skip `n` values and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_front()? {
+                PendingMut::Value(value) => {
+                    self.start += 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                PendingMut::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.start += subtree.count;
+                    } else {
+                        // split the subtree into disjoint borrows, continue with its left part
+                        let Node { value, left, right, .. } = subtree.node_mut().unwrap();    // only non-empty subtrees are pending
+                        if !right.is_empty() {
+                            self.pending.push_front(PendingMut::Subtree(right));
+                        }
+                        self.pending.push_front(PendingMut::Value(value));
+                        if !left.is_empty() {
+                            self.pending.push_front(PendingMut::Subtree(left));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'set,T> DoubleEndedIterator for HiSetIteratorMut<'set,T>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+
+    /// Skip `n` values from the back and return the next one. Whole subtrees are skipped at once, making this O(log n).
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let mut to_skip = n;
+        loop {
+            match self.pending.pop_back()? {
+                PendingMut::Value(value) => {
+                    self.end -= 1;
+                    if to_skip == 0 {
+                        return Some(value)
+                    }
+                    to_skip -= 1;
+                },
+                PendingMut::Subtree(subtree) => {
+                    if subtree.count <= to_skip {
+                        // skip the whole subtree
+                        to_skip -= subtree.count;
+                        self.end -= subtree.count;
+                    } else {
+                        // split the subtree into disjoint borrows, continue with its right part
+                        let Node { value, left, right, .. } = subtree.node_mut().unwrap();    // only non-empty subtrees are pending
+                        if !left.is_empty() {
+                            self.pending.push_back(PendingMut::Subtree(left));
+                        }
+                        self.pending.push_back(PendingMut::Value(value));
+                        if !right.is_empty() {
+                            self.pending.push_back(PendingMut::Subtree(right));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl <'set,T> ExactSizeIterator for HiSetIteratorMut<'set,T>
+{}
+
+impl <'set,T> FusedIterator for HiSetIteratorMut<'set,T>
+{}
+
+impl <'set,T,C> IntoIterator for &'set mut HiSet<T,(),C>
+    where C: Comparator<T>
+{
+    type Item = &'set mut T;
+    type IntoIter = HiSetIteratorMut<'set,T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl <T,C> HiSet<T,(),C>
+    where C: Comparator<T>
+{
+    /// Get iterator over `&mut T`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///  # use hitree::hiset::HiSet;
+    /// let mut s = HiSet::<String>::new();
+    /// s.insert("This");
+    /// s.insert("is");
+    /// s.insert("a");
+    /// s.insert("test!");
+    ///
+    /// let mut i = s.iter_mut();
+    ///
+    /// assert_eq!(i.next(), Some(&mut "This".to_string()));
+    /// assert_eq!(i.next(), Some(&mut "a".to_string()));
+    /// assert_eq!(i.next(), Some(&mut "is".to_string()));
+    /// assert_eq!(i.next(), Some(&mut "test!".to_string()));
+    /// assert_eq!(i.next(), None);
+    ///
+    /// ```
+    pub fn iter_mut(&mut self) -> HiSetIteratorMut<'_,T> {
+        let end = self.root.count;
+        HiSetIteratorMut::new(&mut self.root, 0, end)
+    }
+
+    /// Get a mutable borrow of value from set by index.
+    /// Values in the set are sorted according to their Ord trait,
+    /// index 0 is the smallest value.
+    /// Borrowed value can be any mutable reference type that can be borrowed from T.
+    /// WARNING: You must never change the borrowed value in a way that would affect its ordering according to
+    /// its Ord trait implementation!
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    ///     # use std::cmp::Ordering;
+    ///     # use hitree::hiset::HiSet;
+    ///
+    ///     struct TestValue {
+    ///         ordering: String,
+    ///         data: usize,
+    ///     }
+    ///
+    ///     impl TestValue {
+    ///         pub fn new(ordering: impl Into<String>) -> Self { TestValue { ordering: ordering.into(), data: 0 }}
+    ///         pub fn touch(&mut self) { self.data += 1; }
+    ///     }
+    ///     impl PartialEq for TestValue { fn eq(&self, other: &Self) -> bool { self.ordering.eq(&other.ordering) } }
+    ///     impl Eq for TestValue {}
+    ///     impl PartialOrd for TestValue { fn partial_cmp(&self, other: &Self) -> Option<Ordering> { self.ordering.partial_cmp(&other.ordering) } }
+    ///     impl Ord for TestValue { fn cmp(&self, other: &Self) -> Ordering { self.ordering.cmp(&other.ordering) } }
+    ///
+    ///
+    ///     let mut hiset = HiSet::<TestValue>::new();
+    ///     hiset.insert(TestValue::new("first"));
+    ///     hiset.insert(TestValue::new("second"));
+    ///     hiset.insert(TestValue::new("third"));
+    ///
+    ///     hiset.get_by_index_mut(0).map(|value| value.touch() );
+    ///     hiset.get_by_index_mut(2).map(|value| { value.touch(); value.touch();} );
+    ///
+    ///     assert_eq!(hiset.get_by_index(0).unwrap().data, 1);
+    ///     assert_eq!(hiset.get_by_index(1).unwrap().data, 0);
+    ///     assert_eq!(hiset.get_by_index(2).unwrap().data, 2);
+    /// ```
+    ///
+    pub fn get_by_index_mut<B>(&mut self, index: usize) -> Option<&mut B>
+        where T: BorrowMut<B>,
+              B: ?Sized
+    {
+        let mut index_to_find = index;
+        let mut current_node = self.root.node_mut();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match node.left.count.cmp(&index_to_find) {
+                        Ordering::Greater => {
+                            // index must be in the left subtree
+                            current_node = node.left.node_mut();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some(node.borrow_value_mut())
+                        },
+                        Ordering::Less => {
+                            // index must be in the right subtree
+                            index_to_find = index_to_find - 1 - node.left.count;
+                            current_node = node.right.node_mut();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Borrow mutably value from set by key reference.
+    /// Reference type of key must have the same ordering as `&T` under the comparator of the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut set = HiSet::<String>::new();
+    ///     set.insert("This");
+    ///     set.insert("is");
+    ///     set.insert("a");
+    ///     set.insert("test!");
+    ///
+    ///     assert_eq!(set.get_mut("test!"), Some(&mut "test!".to_string()));
+    ///     assert_eq!(set.get_mut("not there"), None);
+    ///     assert_eq!(set.get_mut(&"This".to_string()), Some(&mut "This".to_string()));
+    ///```
+    pub fn get_mut<KEY>(&mut self, key: &KEY) -> Option<&mut T>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let comparator = &self.comparator;
+        let mut current_node = self.root.node_mut();
+        loop {
+            match current_node {
+                None => return None,
+                Some(node) => {
+                    match comparator.compare(node.value.borrow(), key) {
+                        Ordering::Greater => {
+                            // index must be in the left subtree
+                            current_node = node.left.node_mut();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Some(node.borrow_value_mut::<T>())
+                        },
+                        Ordering::Less => {
+                            // index must be in the right subtree
+                            current_node = node.right.node_mut();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return double ended iterator over `&mut T` in given index range.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from([0,1,2,3,4,5,6].into_iter());
+    ///     let mut r = s.range_by_index_mut(2..=5).map(|v| *v);
+    ///     assert!(r.eq( [2,3,4,5].into_iter() ));
+    /// ```
+    pub fn range_by_index_mut(&mut self, range: impl std::ops::RangeBounds<usize>) -> HiSetIteratorMut<'_,T> {
+        let (start, end) = index_bounds(range, self.root.count);
+
+        HiSetIteratorMut::new(&mut self.root, start, end)
+    }
+
+}
+
+
+impl <T,A,C> Default for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default
+{
+    /// Create new empty `HiSet`, same as [new](`HiSet<T>::new`). Works for augmented sets too.
+    fn default() -> Self {
+        HiSet { root: Ref::default(), comparator: C::default() }
+    }
+}
+
+
+impl <T,A,C,I,X,O> From<I> for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default,
+          I: Iterator<Item=X>,
+          O: Into<T>,
+          X: ToOwned<Owned=O>
+{
+    /// Construct `HiSet<T>` from an `Iterator` of values that can be made into owned instances of `T`.
+    /// Same as [from_vec](`HiSet<T>::from_vec`) on collected values.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hiset::HiSet;
+    /// let s = HiSet::<String>::from( ["This","is","a","test!"].into_iter() );
+    ///
+    /// assert!(s.iter().eq(["This","a","is","test!"].iter()));
+    /// ```
+    fn from(iterator: I) -> Self {
+        iterator.map(|value| value.to_owned().into()).collect()
+    }
+}
+
+
+impl <T,A,C> std::ops::Index<usize> for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Output = T;
+
+    /// Borrow value by index, same as [get_by_index](`HiSet<T>::get_by_index`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hiset::HiSet;
+    /// let s = HiSet::<i32>::from_vec(vec![30,10,20]);
+    /// assert_eq!(s[0], 10);
+    /// assert_eq!(s[2], 30);
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use hitree::hiset::HiSet;
+    /// let s = HiSet::<i32>::from_vec(vec![30,10,20]);
+    /// let _ = s[3];
+    /// ```
+    fn index(&self, index: usize) -> &T {
+        match self.get_by_index(index) {
+            Some(value) => value,
+            None => panic!("index out of bounds: the len is {} but the index is {}", self.len(), index),
+        }
+    }
+}
+
+impl <T,A,C> FromIterator<T> for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T> + Default
+{
+    /// Collect values into a `HiSet`. Of equal values, the first one is kept.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hiset::HiSet;
+    /// let s: HiSet<i32> = [3,1,2,1].into_iter().collect();
+    /// assert!(s.iter().eq([1,2,3].iter()));
+    /// ```
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+        HiSet::from_vec_with_comparator(iter.into_iter().collect(), C::default())
+    }
+}
+
+impl <T,A,C> Extend<T> for HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Insert all values from the iterator. Values already in the set are left untouched.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use hitree::hiset::HiSet;
+    /// let mut s = HiSet::<i32>::from_vec(vec![1,2]);
+    /// s.extend([2,3]);
+    /// assert!(s.iter().eq([1,2,3].iter()));
+    /// ```
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl <'a,T,A,C> Extend<&'a T> for HiSet<T,A,C>
+    where T: 'a + Copy, A: Augment<T>, C: Comparator<T>
+{
+    fn extend<I: IntoIterator<Item=&'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl <T,A,C> Debug for HiSet<T,A,C>
+    where T: Debug, A: Augment<T>, C: Comparator<T>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl <T,A,C> PartialEq for HiSet<T,A,C>
+    where T: PartialEq, A: Augment<T>, C: Comparator<T>
+{
+    /// Sets are equal if they hold equal values in the same iteration order.
+    /// Comparators are not compared, so sets holding the same values ordered differently are not equal.
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl <T,A,C> Eq for HiSet<T,A,C>
+    where T: Eq, A: Augment<T>, C: Comparator<T>
+{}
+
+impl <T,A,C> PartialOrd for HiSet<T,A,C>
+    where T: PartialOrd, A: Augment<T>, C: Comparator<T>
+{
+    /// Compare sets lexicographically by their values in iteration order, using `PartialOrd` of the values.
+    /// Like `eq`, this ignores the comparators.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl <T,A,C> Ord for HiSet<T,A,C>
+    where T: Ord, A: Augment<T>, C: Comparator<T>
+{
+    /// Compare sets lexicographically by their values in iteration order, using `Ord` of the values.
+    /// Like `eq`, this ignores the comparators, so sets are `Equal` exactly when they are equal.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl <T,A,C> Hash for HiSet<T,A,C>
+    where T: Hash, A: Augment<T>, C: Comparator<T>
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for value in self.iter() {
+            value.hash(state);
+        }
+    }
+}
+
+#[test]
+fn test_hiset_std_traits() {
+        use std::collections::HashSet;
+        let a: HiSet<i32> = (0..20).rev().collect();
+        let b = a.clone();
+        assert_eq!(a, b);
+        assert_eq!(format!("{:?}", HiSet::<i32>::from_vec(vec![2,1])), "{1, 2}");
+        let mut c = b.clone();
+        c.extend(&[20]);
+        assert_ne!(a, c);
+        assert!(a < c);
+        let hashes: HashSet<HiSet<i32>> = [a, b, c].into_iter().collect();
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(HiSet::<i32>::default(), HiSet::<i32>::new());
+        // comparisons follow the iteration order, whatever the comparators are
+        fn order(descending: bool) -> impl Fn(&i32, &i32) -> Ordering {
+            move |a, b| if descending { b.cmp(a) } else { a.cmp(b) }
+        }
+        let mut up = HiSet::with_comparator(order(false));
+        let mut down = HiSet::with_comparator(order(true));
+        up.extend([1,2,3]);
+        down.extend([1,2,3]);
+        assert_ne!(up, down);
+        assert_eq!(up.cmp(&down), Ordering::Less);
+        assert_eq!(down.partial_cmp(&up), Some(Ordering::Greater));
+        down.extend([0]);
+        assert!(down.iter().eq([3,2,1,0].iter()));
+        assert!(up < down);
+}
+
+
+//---------------- Set operations --------------------------------------------
+
+// Both sets are walked in the order given by the comparator of `self`, so like keys of a `BTreeMap` that must not
+// change their order, the values of `other` must be ordered the same way. That is only checked in debug builds.
+impl <T,A,C> HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Return true if values of `other` are in the order given by the comparator of `self`. Takes O(m),
+    /// so it is only used in debug assertions.
+    fn same_order(&self, other: &HiSet<T,A,C>) -> bool {
+        other.iter().zip(other.iter().skip(1)).all(|(a, b)| self.comparator.compare(a, b) == Ordering::Less )
+    }
+
+    /// Return iterator over values in `self` or `other`, in ascending order, without duplicates.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!(a.union(&b).eq([1,2,3,4].iter()));
+    /// ```
+    pub fn union<'set>(&'set self, other: &'set HiSet<T,A,C>) -> HiSetUnion<'set,T,A,C> {
+        HiSetUnion { pair: MergePair::new(self, other) }
+    }
+
+    /// Return iterator over values both in `self` and `other`, in ascending order.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    /// Values are borrowed from `self`.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!(a.intersection(&b).eq([3].iter()));
+    /// ```
+    pub fn intersection<'set>(&'set self, other: &'set HiSet<T,A,C>) -> HiSetIntersection<'set,T,A,C> {
+        HiSetIntersection { pair: MergePair::new(self, other) }
+    }
+
+    /// Return iterator over values in `self` but not in `other`, in ascending order.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!(a.difference(&b).eq([1,2].iter()));
+    /// ```
+    pub fn difference<'set>(&'set self, other: &'set HiSet<T,A,C>) -> HiSetDifference<'set,T,A,C> {
+        HiSetDifference { pair: MergePair::new(self, other) }
+    }
+
+    /// Return iterator over values in either `self` or `other` but not in both, in ascending order.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!(a.symmetric_difference(&b).eq([1,2,4].iter()));
+    /// ```
+    pub fn symmetric_difference<'set>(&'set self, other: &'set HiSet<T,A,C>) -> HiSetSymmetricDifference<'set,T,A,C> {
+        HiSetSymmetricDifference { pair: MergePair::new(self, other) }
+    }
+
+    /// Add all values from `other` missing in `self`, cloning them.
+    /// The set is rebuilt from the merged values in O(n + m), values already in `self` are kept.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut a = HiSet::<i32>::from([1,3,5].into_iter());
+    ///     a.union_with(&HiSet::from([2,3,4].into_iter()));
+    ///     assert!(a.iter().eq([1,2,3,4,5].iter()));
+    /// ```
+    pub fn union_with(&mut self, other: &HiSet<T,A,C>)
+        where T: Clone
+    {
+        debug_assert!(self.same_order(other), "other set is ordered differently");
+        let capacity = self.len() + other.len();
+        let mut merged = Rebuild::new(&mut self.root, capacity);
+        let mut theirs = other.iter().peekable();
+        while let (Some(my_value), Some(their_value)) = (merged.rest.peek(), theirs.peek()) {
+            match self.comparator.compare(my_value, their_value) {
+                Ordering::Less => merged.values.push(merged.rest.next().unwrap()),
+                Ordering::Greater => merged.values.push(theirs.next().unwrap().clone()),
+                Ordering::Equal => {
+                    theirs.next();
+                    merged.values.push(merged.rest.next().unwrap());
+                }
+            }
+        }
+        // at most one side has values left, the rest of mine is appended when the guard is dropped
+        merged.values.extend(theirs.cloned());
+    }
+
+    /// Keep only values that are also in `other`.
+    /// The set is rebuilt from the remaining values in O(n + m).
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let mut a = HiSet::<i32>::from([1,3,5].into_iter());
+    ///     a.intersect_with(&HiSet::from([2,3,4,5].into_iter()));
+    ///     assert!(a.iter().eq([3,5].iter()));
+    /// ```
+    pub fn intersect_with(&mut self, other: &HiSet<T,A,C>) {
+        debug_assert!(self.same_order(other), "other set is ordered differently");
+        let mut theirs = other.iter().peekable();
+        let capacity = self.len().min(other.len());
+        let mut kept = Rebuild::new(&mut self.root, capacity);
+        while let Some(value) = kept.rest.peek() {
+            while theirs.next_if(|their_value| self.comparator.compare(their_value, value) == Ordering::Less ).is_some() {}
+            if theirs.next_if(|their_value| self.comparator.compare(their_value, value) == Ordering::Equal ).is_some() {
+                kept.values.push(kept.rest.next().unwrap());
+            } else {
+                kept.rest.next();
+            }
+        }
+    }
+
+    /// Return true if all values in `self` are also in `other`.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,3].into_iter());
+    ///     let b = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     assert!(a.is_subset(&b));
+    ///     assert!(!b.is_subset(&a));
+    /// ```
+    pub fn is_subset(&self, other: &HiSet<T,A,C>) -> bool {
+        self.len() <= other.len() && self.difference(other).next().is_none()
+    }
+
+    /// Return true if all values in `other` are also in `self`.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    pub fn is_superset(&self, other: &HiSet<T,A,C>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Return true if `self` and `other` have no values in common.
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,3].into_iter());
+    ///     assert!(a.is_disjoint(&HiSet::from([2,4].into_iter())));
+    ///     assert!(!a.is_disjoint(&HiSet::from([3,4].into_iter())));
+    /// ```
+    pub fn is_disjoint(&self, other: &HiSet<T,A,C>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+
+/// Two iterators walked side by side in order, shared by the set operation iterators.
+struct MergePair<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    a: std::iter::Peekable<HiSetIterator<'set,T,A>>,
+    b: std::iter::Peekable<HiSetIterator<'set,T,A>>,
+    comparator: &'set C,
+}
+
+impl <'set,T,A,C> MergePair<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Both sets are walked in the order given by the comparator of `a`.
+    fn new(a: &'set HiSet<T,A,C>, b: &'set HiSet<T,A,C>) -> Self {
+        debug_assert!(a.same_order(b), "other set is ordered differently");
+        MergePair { a: a.iter().peekable(), b: b.iter().peekable(), comparator: &a.comparator }
+    }
+
+    /// Return the smallest value from either iterator, or from both if they are equal.
+    fn next_pair(&mut self) -> (Option<&'set T>, Option<&'set T>) {
+        let order = match (self.a.peek(), self.b.peek()) {
+            (Some(a), Some(b)) => self.comparator.compare(a, b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return (None, None),
+        };
+        match order {
+            Ordering::Less => (self.a.next(), None),
+            Ordering::Greater => (None, self.b.next()),
+            Ordering::Equal => (self.a.next(), self.b.next()),
+        }
+    }
+
+    fn lens(&self) -> (usize, usize) {
+        (self.a.len(), self.b.len())
+    }
+}
+
+/// Iterator over values in either of two sets. Returned by [union](`HiSet<T>::union`).
+pub struct HiSetUnion<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    pair: MergePair<'set,T,A,C>,
+}
+
+impl <'set,T,A,C> Iterator for HiSetUnion<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'set T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (a, b) = self.pair.next_pair();
+        a.or(b)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.pair.lens();
+        (a.max(b), Some(a + b))
+    }
+}
+
+impl <'set,T,A,C> FusedIterator for HiSetUnion<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{}
+
+/// Iterator over values in both of two sets. Returned by [intersection](`HiSet<T>::intersection`).
+pub struct HiSetIntersection<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    pair: MergePair<'set,T,A,C>,
+}
+
+impl <'set,T,A,C> Iterator for HiSetIntersection<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'set T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.pair.next_pair() {
+                (Some(a), Some(_)) => return Some(a),
+                (None, None) => return None,
+                _ => {},
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.pair.lens();
+        (0, Some(a.min(b)))
+    }
+}
+
+impl <'set,T,A,C> FusedIterator for HiSetIntersection<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{}
+
+/// Iterator over values in the first set but not in the second one. Returned by [difference](`HiSet<T>::difference`).
+pub struct HiSetDifference<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    pair: MergePair<'set,T,A,C>,
+}
+
+impl <'set,T,A,C> Iterator for HiSetDifference<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'set T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.pair.next_pair() {
+                (Some(a), None) => return Some(a),
+                (None, None) => return None,
+                (None, Some(_)) if self.pair.a.peek().is_none() => return None,   // no need to walk the rest of b
+                _ => {},
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.pair.lens();
+        (a.saturating_sub(b), Some(a))
+    }
+}
+
+impl <'set,T,A,C> FusedIterator for HiSetDifference<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{}
+
+/// Iterator over values in exactly one of two sets.
+/// Returned by [symmetric_difference](`HiSet<T>::symmetric_difference`).
+pub struct HiSetSymmetricDifference<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    pair: MergePair<'set,T,A,C>,
+}
+
+impl <'set,T,A,C> Iterator for HiSetSymmetricDifference<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    type Item = &'set T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.pair.next_pair() {
+                (Some(value), None) | (None, Some(value)) => return Some(value),
+                (None, None) => return None,
+                (Some(_), Some(_)) => {},
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.pair.lens();
+        (0, Some(a + b))
+    }
+}
+
+impl <'set,T,A,C> FusedIterator for HiSetSymmetricDifference<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{}
+
+
+impl <T,A,C> std::ops::BitOr<&HiSet<T,A,C>> for &HiSet<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    type Output = HiSet<T,A,C>;
+
+    /// Return union of two sets as a new set, built in O(n + m).
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!((&a | &b).iter().eq([1,2,3,4].iter()));
+    /// ```
+    fn bitor(self, other: &HiSet<T,A,C>) -> HiSet<T,A,C> {
+        HiSet { root: Ref::from_sorted_vec(self.union(other).cloned().collect()), comparator: self.comparator.clone() }
+    }
+}
+
+impl <T,A,C> std::ops::BitAnd<&HiSet<T,A,C>> for &HiSet<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    type Output = HiSet<T,A,C>;
+
+    /// Return intersection of two sets as a new set, built in O(n + m).
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!((&a & &b).iter().eq([3].iter()));
+    /// ```
+    fn bitand(self, other: &HiSet<T,A,C>) -> HiSet<T,A,C> {
+        HiSet { root: Ref::from_sorted_vec(self.intersection(other).cloned().collect()), comparator: self.comparator.clone() }
+    }
+}
+
+impl <T,A,C> std::ops::Sub<&HiSet<T,A,C>> for &HiSet<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    type Output = HiSet<T,A,C>;
+
+    /// Return difference of two sets as a new set, built in O(n + m).
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!((&a - &b).iter().eq([1,2].iter()));
+    /// ```
+    fn sub(self, other: &HiSet<T,A,C>) -> HiSet<T,A,C> {
+        HiSet { root: Ref::from_sorted_vec(self.difference(other).cloned().collect()), comparator: self.comparator.clone() }
+    }
+}
+
+impl <T,A,C> std::ops::BitXor<&HiSet<T,A,C>> for &HiSet<T,A,C>
+    where T: Clone, A: Augment<T>, C: Comparator<T> + Clone
+{
+    type Output = HiSet<T,A,C>;
+
+    /// Return symmetric difference of two sets as a new set, built in O(n + m).
+    /// Both sets must be ordered the same way, by equally configured comparators, which is only checked in debug builds.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let a = HiSet::<i32>::from([1,2,3].into_iter());
+    ///     let b = HiSet::<i32>::from([3,4].into_iter());
+    ///     assert!((&a ^ &b).iter().eq([1,2,4].iter()));
+    /// ```
+    fn bitxor(self, other: &HiSet<T,A,C>) -> HiSet<T,A,C> {
+        HiSet { root: Ref::from_sorted_vec(self.symmetric_difference(other).cloned().collect()), comparator: self.comparator.clone() }
+    }
+}
+
+
+#[test]
+fn test_hiset_set_operations() {
+        use std::collections::BTreeSet;
+        let patterns: Vec<Vec<i32>> = vec![
+            vec![], vec![5], (0..30).collect(), (0..30).step_by(3).collect(), (10..50).step_by(2).collect(), (40..60).collect(),
+        ];
+        for a in patterns.iter() {
+            for b in patterns.iter() {
+                let (ha, hb): (HiSet<i32>, HiSet<i32>) = (a.iter().copied().collect(), b.iter().copied().collect());
+                let (ba, bb): (BTreeSet<i32>, BTreeSet<i32>) = (a.iter().copied().collect(), b.iter().copied().collect());
+                assert!(ha.union(&hb).eq(ba.union(&bb)));
+                assert!(ha.intersection(&hb).eq(ba.intersection(&bb)));
+                assert!(ha.difference(&hb).eq(ba.difference(&bb)));
+                assert!(ha.symmetric_difference(&hb).eq(ba.symmetric_difference(&bb)));
+                assert!((&ha | &hb).into_iter().eq(&ba | &bb));
+                assert!((&ha & &hb).into_iter().eq(&ba & &bb));
+                assert!((&ha - &hb).into_iter().eq(&ba - &bb));
+                assert!((&ha ^ &hb).into_iter().eq(&ba ^ &bb));
+                assert_eq!(ha.is_subset(&hb), ba.is_subset(&bb));
+                assert_eq!(ha.is_superset(&hb), ba.is_superset(&bb));
+                assert_eq!(ha.is_disjoint(&hb), ba.is_disjoint(&bb));
+                let mut u = ha.clone();
+                u.union_with(&hb);
+                assert_eq!(u, &ha | &hb);
+                let mut i = ha.clone();
+                i.intersect_with(&hb);
+                assert_eq!(i, &ha & &hb);
+            }
+        }
+}
+
+#[test]
+fn test_hiset_set_operations_panic_safety() {
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        // comparator panicking once it runs out of comparisons
+        let budget = Cell::new(usize::MAX);
+        let counting = |a: &i32, b: &i32| {
+            budget.set(budget.get().checked_sub(1).expect("out of comparisons"));
+            a.cmp(b)
+        };
+        let set = |values: &mut dyn Iterator<Item=i32>| {
+            let mut set = HiSet::with_comparator(counting);
+            set.extend(values);
+            set
+        };
+        let valid = |set: &HiSet<i32,(),_>| set.len() == set.iter().count() && set.iter().zip(set.iter().skip(1)).all(|(a, b)| a < b );
+        for limit in 0..150 {
+            let (mut evens, odds) = (set(&mut (0..30).map(|i| i * 2)), set(&mut (0..30).map(|i| i * 2 + 1)));
+            budget.set(limit);
+            let result = catch_unwind(AssertUnwindSafe(|| evens.union_with(&odds) ));
+            budget.set(usize::MAX);
+            assert!(valid(&evens));
+            assert!((0..30).all(|i| evens.contains(&(i * 2))));
+            assert!(result.is_err() || evens.len() == 60);
+
+            let (mut low, high) = (set(&mut (0..40)), set(&mut (20..60)));
+            budget.set(limit);
+            let result = catch_unwind(AssertUnwindSafe(|| low.intersect_with(&high) ));
+            budget.set(usize::MAX);
+            assert!(valid(&low));
+            assert!((20..40).all(|value| low.contains(&value)));
+            assert!(result.is_err() || low.len() == 20);
+        }
+}
+
+
+//---------------- Order statistics ------------------------------------------
+
+impl <T,A,C> HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Return number of values falling within given key bounds in O(log n).
+    /// Bounds don't need to be present in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from_sorted_iter((0..100).map(|i| i * 10)).unwrap();
+    ///     assert_eq!(s.count_range(95..=200), 11);
+    ///     assert_eq!(s.count_range(..), 100);
+    /// ```
+    pub fn count_range<KEY,R>(&self, range: R) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        self.index_range(range).len()
+    }
+
+    /// Return the `q`-quantile of the set, for `q` between 0.0 and 1.0. Returns `None` if the set is empty.
+    ///
+    /// The quantile lies at position `q * (len - 1)`, between the values with indexes `floor` and `ceil`
+    /// of that position. This is the definition used by `numpy.quantile` and R's default (type 7).
+    /// The returned [Quantile] holds both neighbouring values, use its methods to choose rounding or interpolation.
+    /// Takes O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between 0.0 and 1.0.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let s = HiSet::<u32>::from([10_u32,20,30,40,50].into_iter());
+    ///     let q = s.quantile(0.3).unwrap();     // position 1.2
+    ///     assert_eq!(q.lower(), (1, &20));
+    ///     assert_eq!(q.higher(), (2, &30));
+    ///     assert_eq!(q.nearest(), (1, &20));
+    ///     assert!((q.linear() - 22.0).abs() < 1e-9);
+    /// ```
+    pub fn quantile(&self, q: f64) -> Option<Quantile<'_,T>> {
+        assert!((0.0..=1.0).contains(&q), "quantile must be between 0.0 and 1.0, got {}", q);
+        let last = self.len().checked_sub(1)?;
+        let position = q * last as f64;
+        let lower_index = (position.floor() as usize).min(last);
+        let upper_index = (position.ceil() as usize).min(last);
+        Some(Quantile {
+            lower: (lower_index, self.get_by_index(lower_index)?),
+            upper: (upper_index, self.get_by_index(upper_index)?),
+            fraction: position - lower_index as f64,
+        })
+    }
+
+    /// Return quantiles for each of the given `qs`, see [quantile](`HiSet<T>::quantile`).
+    /// Returns `None` if the set is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of `qs` is not between 0.0 and 1.0.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let s = HiSet::<u32>::from_sorted_iter(1..=101).unwrap();
+    ///     let quartiles: Vec<u32> = s.quantiles(&[0.25, 0.5, 0.75]).unwrap().iter().map(|q| *q.nearest().1 ).collect();
+    ///     assert_eq!(quartiles, vec![26, 51, 76]);
+    /// ```
+    pub fn quantiles(&self, qs: &[f64]) -> Option<Vec<Quantile<'_,T>>> {
+        qs.iter().map(|q| self.quantile(*q) ).collect()
+    }
+
+    /// Return the `p`-th percentile, for `p` between 0.0 and 100.0. Same as `quantile(p / 100.0)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 0.0 and 100.0.
+    pub fn percentile(&self, p: f64) -> Option<Quantile<'_,T>> {
+        assert!((0.0..=100.0).contains(&p), "percentile must be between 0.0 and 100.0, got {}", p);
+        self.quantile(p / 100.0)
+    }
+
+    /// Return the median, same as `quantile(0.5)`. For an even number of values,
+    /// [lower](`Quantile::lower`) and [higher](`Quantile::higher`) are the two middle values.
+    ///
+    /// # Examples:
+    /// ```
+    ///     # use hitree::hiset::HiSet;
+    ///     let s = HiSet::<u32>::from([4_u32,1,3,2].into_iter());
+    ///     let median = s.median().unwrap();
+    ///     assert_eq!((median.lower().1, median.higher().1), (&2, &3));
+    ///     assert_eq!(median.midpoint(), 2.5);
+    ///     assert!(HiSet::<u32>::new().median().is_none());
+    /// ```
+    pub fn median(&self) -> Option<Quantile<'_,T>> {
+        self.quantile(0.5)
+    }
+}
+
+
+/// Quantile of a `HiSet`, lying between two neighbouring values.
+/// Returned by [quantile](`HiSet<T>::quantile`) and related methods.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantile<'set,T> {
+    lower:      (usize, &'set T),
+    upper:      (usize, &'set T),
+    fraction:   f64,            // position of the quantile between lower and upper, from 0.0 to below 1.0
+}
+
+impl <'set,T> Quantile<'set,T> {
+    /// Return index and value just at or below the quantile position.
+    pub fn lower(&self) -> (usize, &'set T) {
+        self.lower
+    }
+
+    /// Return index and value just at or above the quantile position.
+    pub fn higher(&self) -> (usize, &'set T) {
+        self.upper
+    }
+
+    /// Return index and value nearest to the quantile position. Ties are resolved towards the even index.
+    pub fn nearest(&self) -> (usize, &'set T) {
+        if self.fraction < 0.5 || (self.fraction == 0.5 && self.lower.0 % 2 == 0) {
+            self.lower
+        } else {
+            self.upper
+        }
+    }
+
+    /// Return the exact quantile position as a fractional index.
+    pub fn position(&self) -> f64 {
+        self.lower.0 as f64 + self.fraction
+    }
+
+    /// Return the quantile linearly interpolated between lower and higher value.
+    pub fn linear(&self) -> f64
+        where T: Copy + Into<f64>
+    {
+        let (lower, upper): (f64, f64) = ((*self.lower.1).into(), (*self.upper.1).into());
+        lower + (upper - lower) * self.fraction
+    }
+
+    /// Return the average of lower and higher value.
+    pub fn midpoint(&self) -> f64
+        where T: Copy + Into<f64>
+    {
+        let (lower, upper): (f64, f64) = ((*self.lower.1).into(), (*self.upper.1).into());
+        (lower + upper) / 2.0
+    }
+}
+
+
+#[test]
+fn test_hiset_quantiles() {
+        for len in 1..30_u32 {
+            let s = HiSet::<u32>::from_sorted_iter((0..len).map(|i| i * 2)).unwrap();
+            for step in 0..=20 {
+                let q = step as f64 / 20.0;
+                let quantile = s.quantile(q).unwrap();
+                let position = q * (len - 1) as f64;
+                assert!((quantile.position() - position).abs() < 1e-9);
+                assert!((quantile.linear() - position * 2.0).abs() < 1e-9);
+                let (lower, higher, nearest) = (quantile.lower(), quantile.higher(), quantile.nearest());
+                assert_eq!(*lower.1, lower.0 as u32 * 2);
+                assert_eq!(*higher.1, higher.0 as u32 * 2);
+                assert!(lower.0 as f64 <= position && position <= higher.0 as f64 && higher.0 - lower.0 <= 1);
+                assert!((nearest.0 as f64 - position).abs() <= 0.5);
+            }
+            let median = s.median().unwrap();
+            assert_eq!(median.midpoint(), (len - 1) as f64);
+            assert_eq!(s.percentile(100.0).unwrap().lower(), (len as usize - 1, &((len - 1) * 2)));
+        }
+        assert!(HiSet::<u32>::new().quantiles(&[0.5]).is_none());
+        assert_eq!(HiSet::<u32>::from([1_u32,2].into_iter()).quantile(0.5).unwrap().nearest(), (0, &1));
+}
+
+#[test]
+#[should_panic]
+fn test_hiset_quantile_out_of_range() {
+        HiSet::<u32>::from([1_u32,2].into_iter()).quantile(1.5);
+}
+
+
+//---------------- Aggregates ------------------------------------------------
+
+impl <T,A,C> HiSet<T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Return aggregate of values in given index range, or `None` if the range is empty.
+    /// Combines the aggregates of O(log n) subtrees, regardless of the size of the range.
+    ///
+    /// # Examples:
+    /// ```
+    ///     use hitree::augment::Augment;
+    ///     use hitree::hiset::HiSet;
+    ///
+    ///     /// Minimum and maximum of the second field
+    ///     struct MinMax;
+    ///
+    ///     impl Augment<(u32,i64)> for MinMax {
+    ///         type Value = (i64,i64);
+    ///         fn leaf(value: &(u32,i64)) -> (i64,i64) { (value.1, value.1) }
+    ///         fn combine(left: (i64,i64), right: (i64,i64)) -> (i64,i64) { (left.0.min(right.0), left.1.max(right.1)) }
+    ///     }
+    ///
+    ///     let samples: HiSet<(u32,i64), MinMax> = (0..100).map(|time| (time, (time as i64 - 40).pow(2)) ).collect();
+    ///     assert_eq!(samples.aggregate_by_index(30..=50), Some((0, 100)));
+    ///     assert_eq!(samples.aggregate_by_index(100..), None);
+    /// ```
+    pub fn aggregate_by_index(&self, range: impl std::ops::RangeBounds<usize>) -> Option<A::Value> {
+        let (start, end) = index_bounds(range, self.root.count);
+        self.root.aggregate_range(start, end)
+    }
+
+    /// Return aggregate of values falling within given key bounds, or `None` if there are no such values.
+    /// Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     use hitree::augment::Augment;
+    ///     use hitree::hiset::HiSet;
+    ///
+    ///     struct Sum;
+    ///
+    ///     impl Augment<u64> for Sum {
+    ///         type Value = u64;
+    ///         fn leaf(value: &u64) -> u64 { *value }
+    ///         fn combine(left: u64, right: u64) -> u64 { left + right }
+    ///     }
+    ///
+    ///     let mut s: HiSet<u64, Sum> = (1..=10).collect();
+    ///     assert_eq!(s.aggregate(3..=5), Some(12));
+    ///     s.take(&4);
+    ///     assert_eq!(s.aggregate(3..=5), Some(8));
+    ///     assert_eq!(s.aggregate(..), Some(51));
+    /// ```
+    pub fn aggregate<KEY,R>(&self, range: R) -> Option<A::Value>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>, R: std::ops::RangeBounds<KEY>
+    {
+        let std::ops::Range { start, end } = self.index_range(range);
+        self.root.aggregate_range(start, end)
+    }
+}
+
+
+#[cfg(test)]
+struct TestSum;
+
+#[cfg(test)]
+impl Augment<u32> for TestSum {
+    type Value = u64;
+    fn leaf(value: &u32) -> u64 { *value as u64 }
+    fn combine(left: u64, right: u64) -> u64 { left + right }
+}
+
+#[test]
+fn test_hiset_aggregate() {
+        fn check(r: &Ref<u32,TestSum>) -> u64 {
+            match r.node() {
+                None => 0,
+                Some(node) => {
+                    let sum = check(&node.left) + node.value as u64 + check(&node.right);
+                    assert_eq!(node.aggregate, sum);
+                    sum
+                },
+            }
+        }
+        let mut s = HiSet::<u32,TestSum>::default();
+        for value in (0..200_u32).map(|i| (i * 37) % 200) {
+            s.insert(value);
+            check(&s.root);
+        }
+        for start in 0..=200 {
+            for end in (start..=201).step_by(7) {
+                let expected: u64 = (start..end.min(200)).map(|i| i as u64 ).sum();
+                let expected = if expected == 0 && start >= end.min(200) { None } else { Some(expected) };
+                assert_eq!(s.aggregate_by_index(start as usize..end as usize), expected);
+            }
+        }
+        assert_eq!(s.aggregate(10..20), Some(145));
+        s.take_first();
+        s.take_last();
+        s.take_by_index(50);
+        s.take(&100);
+        check(&s.root);
+        let mut high = s.split_off_index(100);
+        check(&s.root);
+        check(&high.root);
+        high.retain(|value| value % 2 == 0);
+        check(&high.root);
+        s.append(&mut high);
+        check(&s.root);
+        s.remove_range(50..150);
+        check(&s.root);
+        assert_eq!(s.aggregate(..), Some(s.iter().map(|value| *value as u64 ).sum()));
+}
+
+
+//---------------- Weights ---------------------------------------------------
+
+impl <T,C> HiSet<T,Weight,C>
+    where T: Weighted, C: Comparator<T>
+{
+    /// Return sum of weights of all values in the set.
+    ///
+    /// # Examples:
+    /// ```
+    ///     use hitree::augment::{Weight, Weighted};
+    ///     use hitree::hiset::HiSet;
+    ///
+    ///     #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    ///     struct Task { name: &'static str, priority: u32 }
+    ///
+    ///     impl Weighted for Task {
+    ///         type Weight = u32;
+    ///         fn weight(&self) -> u32 { self.priority }
+    ///     }
+    ///
+    ///     let mut tasks = HiSet::<Task,Weight>::default();
+    ///     tasks.insert(Task { name: "backup", priority: 1 });
+    ///     tasks.insert(Task { name: "compact", priority: 3 });
+    ///     tasks.insert(Task { name: "index", priority: 6 });
+    ///     assert_eq!(tasks.total_weight(), 10);
+    ///
+    ///     // pick a task with probability proportional to its priority, given a random number in 0..total_weight()
+    ///     let pick = |random: u32| tasks.get_by_index::<Task>(tasks.index_at_weight(random).unwrap()).unwrap().name;
+    ///     assert_eq!(pick(0), "backup");
+    ///     assert_eq!(pick(1), "compact");
+    ///     assert_eq!(pick(3), "compact");
+    ///     assert_eq!(pick(4), "index");
+    ///     assert_eq!(pick(9), "index");
+    /// ```
+    pub fn total_weight(&self) -> T::Weight {
+        self.root.node().map(|node| node.aggregate ).unwrap_or_default()
+    }
+
+    /// Return sum of weights of values with index lower than `index`. Takes O(log n).
+    ///
+    /// # Examples:
+    /// ```
+    ///     use hitree::augment::{Weight, Weighted};
+    ///     use hitree::hiset::HiSet;
+    ///
+    ///     #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    ///     struct Weighs(u32);
+    ///
+    ///     impl Weighted for Weighs {
+    ///         type Weight = u32;
+    ///         fn weight(&self) -> u32 { self.0 }
+    ///     }
+    ///
+    ///     let s: HiSet<Weighs,Weight> = (1..=10).map(Weighs).collect();
+    ///     assert_eq!(s.weight_before(0), 0);
+    ///     assert_eq!(s.weight_before(3), 1 + 2 + 3);
+    ///     assert_eq!(s.weight_before(100), 55);
+    /// ```
+    pub fn weight_before(&self, index: usize) -> T::Weight {
+        self.aggregate_by_index(..index).unwrap_or_default()
+    }
+
+    /// Return index of the value at which the cumulative weight crosses `weight`:
+    /// the value with index `i` such that `weight_before(i) <= weight < weight_before(i + 1)`.
+    /// Values with zero weight are never returned.
+    /// Returns `None` if `weight` is negative or not lower than [total_weight](`HiSet<T,Weight>::total_weight`).
+    /// Takes O(log n).
+    pub fn index_at_weight(&self, weight: T::Weight) -> Option<usize> {
+        // also rejects NaN weights
+        if !matches!(weight.partial_cmp(&T::Weight::default()), Some(Ordering::Greater | Ordering::Equal)) {
+            return None
+        }
+        let mut weight_to_find = weight;
+        let mut index = 0;
+        let mut current_node = self.root.node();
+        while let Some(node) = current_node {
+            let left_weight = node.left.node().map(|left| left.aggregate ).unwrap_or_default();
+            if weight_to_find < left_weight {
+                // crossing is in the left subtree
+                current_node = node.left.node();
+                continue
+            }
+            weight_to_find = weight_to_find - left_weight;
+            let value_weight = node.value.weight();
+            if weight_to_find < value_weight {
+                // crossing is at this node
+                return Some(index + node.left.count)
+            }
+            // crossing is in the right subtree
+            weight_to_find = weight_to_find - value_weight;
+            index += node.left.count + 1;
+            current_node = node.right.node();
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct TestWeighted(u32, u32);
+
+#[cfg(test)]
+impl Weighted for TestWeighted {
+    type Weight = u32;
+    fn weight(&self) -> u32 { self.1 }
+}
+
+#[test]
+fn test_hiset_weights() {
+        let mut s = HiSet::<TestWeighted,Weight>::default();
+        for key in (0..100).map(|i| (i * 37) % 100) {
+            s.insert(TestWeighted(key, key % 4));
+        }
+        s.take(&TestWeighted(50, 2));
+        let weights: Vec<u32> = s.iter().map(|value| value.1 ).collect();
+        let mut cumulative = 0;
+        for (index, weight) in weights.iter().enumerate() {
+            assert_eq!(s.weight_before(index), cumulative);
+            for w in cumulative..cumulative + weight {
+                assert_eq!(s.index_at_weight(w), Some(index));
+            }
+            cumulative += weight;
+        }
+        assert_eq!(s.total_weight(), cumulative);
+        assert_eq!(s.weight_before(weights.len()), cumulative);
+        assert_eq!(s.index_at_weight(cumulative), None);
+        assert_eq!(HiSet::<TestWeighted,Weight>::default().index_at_weight(0), None);
+}
+
+
+//---------------- Cursor ----------------------------------------------------
+
+/// Cursor pointing at a value in a `HiSet`, or at the ghost position between the last and the first value.
+/// Moving to a neighbouring value takes amortized O(1), as the cursor keeps the path from the root to the current node.
+///
+/// Returned by [cursor_at](`HiSet<T>::cursor_at`) and [cursor_at_key](`HiSet<T>::cursor_at_key`).
+pub struct Cursor<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    set:    &'set HiSet<T,A,C>,
+    path:   Vec<&'set Node<T,A>>,     // nodes from the root to the current node, empty at the ghost position
+    index:  usize,                  // index of the current node, len() at the ghost position
+}
+
+impl <'set,T,A,C> Clone for Cursor<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    fn clone(&self) -> Self {
+        Cursor { set: self.set, path: self.path.clone(), index: self.index }
+    }
+}
+
+impl <'set,T,A,C> Cursor<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Return index of the current value, or `None` at the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        if self.path.is_empty() { None } else { Some(self.index) }
+    }
+
+    /// Borrow the current value, or return `None` at the ghost position.
+    pub fn current(&self) -> Option<&'set T> {
+        self.path.last().map(|node| &node.value )
+    }
+
+    /// Borrow the value after the current one. At the ghost position, this is the first value.
+    pub fn peek_next(&self) -> Option<&'set T> {
+        match self.index() {
+            None => self.set.get_by_index(0),
+            Some(index) => self.set.get_by_index(index + 1),
+        }
+    }
+
+    /// Borrow the value before the current one. At the ghost position, this is the last value.
+    pub fn peek_prev(&self) -> Option<&'set T> {
+        match self.index() {
+            None => self.set.len().checked_sub(1).and_then(|index| self.set.get_by_index(index) ),
+            Some(index) => index.checked_sub(1).and_then(|index| self.set.get_by_index(index) ),
+        }
+    }
+
+    /// Move to the value with given index, or to the ghost position if the index is out of bounds.
+    pub fn seek_index(&mut self, index: usize) {
+        self.path.clear();
+        if index >= self.set.len() {
+            self.index = self.set.len();
+            return
+        }
+        self.index = index;
+        let mut index_to_find = index;
+        let mut current_node = self.set.root.node();
+        while let Some(node) = current_node {
+            self.path.push(node);
+            match node.left.count.cmp(&index_to_find) {
+                Ordering::Greater => {
+                    // index must be in the left subtree
+                    current_node = node.left.node();
+                },
+                Ordering::Equal => {
+                    // found it, its this node
+                    return
+                },
+                Ordering::Less => {
+                    // index must be in the right subtree
+                    index_to_find = index_to_find - 1 - node.left.count;
+                    current_node = node.right.node();
+                }
+            }
+        }
+    }
+
+    /// Move to the value equal to the key, or the smallest greater value if the key is not in the set.
+    /// Return true if the key was found.
+    pub fn seek_key<KEY>(&mut self, key: &KEY) -> bool
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let (index, found) = match self.set.root.find_index(key, &self.set.comparator) {
+            Ok(index) => (index, true),
+            Err(index) => (index, false),
+        };
+        self.seek_index(index);
+        found
+    }
+
+    /// Move to the next value. Moves from the last value to the ghost position, and from the ghost position
+    /// to the first value.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let s = HiSet::<i32>::from_sorted_iter(0..3).unwrap();
+    ///     let mut c = s.cursor_at(2);
+    ///     c.move_next();
+    ///     assert_eq!(c.current(), None);
+    ///     c.move_next();
+    ///     assert_eq!(c.current(), Some(&0));
+    /// ```
+    pub fn move_next(&mut self) {
+        match self.path.last().copied() {
+            None => self.seek_index(0),
+            Some(node) => {
+                if let Some(mut next) = node.right.node() {
+                    // next is the leftmost node of the right subtree
+                    self.path.push(next);
+                    while let Some(left) = next.left.node() {
+                        self.path.push(left);
+                        next = left;
+                    }
+                } else {
+                    // next is the first ancestor we reach from its left subtree
+                    while let Some(child) = self.path.pop() {
+                        if let Some(parent) = self.path.last() {
+                            if parent.left.node().map_or(false, |left| std::ptr::eq(left, child)) {
+                                break
+                            }
+                        }
+                    }
+                }
+                self.index += 1;
+            }
+        }
+    }
+
+    /// Move to the previous value. Moves from the first value to the ghost position, and from the ghost position
+    /// to the last value.
+    pub fn move_prev(&mut self) {
+        match self.path.last().copied() {
+            None => self.seek_index(self.set.len().wrapping_sub(1)),   // empty set stays at the ghost position
+            Some(node) => {
+                if let Some(mut prev) = node.left.node() {
+                    // previous is the rightmost node of the left subtree
+                    self.path.push(prev);
+                    while let Some(right) = prev.right.node() {
+                        self.path.push(right);
+                        prev = right;
+                    }
+                    self.index -= 1;
+                } else {
+                    // previous is the first ancestor we reach from its right subtree
+                    while let Some(child) = self.path.pop() {
+                        if let Some(parent) = self.path.last() {
+                            if parent.right.node().map_or(false, |right| std::ptr::eq(right, child)) {
+                                break
+                            }
+                        }
+                    }
+                    self.index = if self.path.is_empty() { self.set.len() } else { self.index - 1 };
+                }
+            }
+        }
+    }
+}
+
+
+/// Cursor pointing at a value in a `HiSet`, or at the ghost position between the last and the first value,
+/// that can modify the set.
+/// The cursor keeps only the index of the current value and all the nodes stay in the tree, so the set is complete
+/// at any time, even if the cursor is forgotten. Moving takes O(1). Reading a value takes a single O(log n) descent,
+/// and so does removing or inserting one, as every modification updates the counts on the path from the root and
+/// can rebalance the tree. Use [as_cursor](`CursorMut::as_cursor`) for faster read-only traversal.
+///
+/// Returned by [cursor_mut_at](`HiSet<T>::cursor_mut_at`) and [cursor_mut_at_key](`HiSet<T>::cursor_mut_at_key`).
+pub struct CursorMut<'set,T,A = (),C = OrdComparator>
+    where A: Augment<T>, C: Comparator<T>
+{
+    set:    &'set mut HiSet<T,A,C>,
+    index:  usize,                  // index of the current value, len() at the ghost position
+}
+
+impl <'set,T,A,C> CursorMut<'set,T,A,C>
+    where A: Augment<T>, C: Comparator<T>
+{
+    /// Return index of the current value, or `None` at the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        if self.index < self.set.len() { Some(self.index) } else { None }
+    }
+
+    /// Borrow the current value, or return `None` at the ghost position.
+    pub fn current(&self) -> Option<&T> {
+        self.set.get_by_index(self.index)
+    }
+
+
+    /// Borrow the value after the current one. At the ghost position, this is the first value.
+    pub fn peek_next(&self) -> Option<&T> {
+        match self.index() {
+            None => self.set.get_by_index(0),
+            Some(index) => self.set.get_by_index(index + 1),
+        }
+    }
+
+    /// Borrow the value before the current one. At the ghost position, this is the last value.
+    pub fn peek_prev(&self) -> Option<&T> {
+        self.index.checked_sub(1).and_then(|index| self.set.get_by_index(index) )
+    }
+
+    /// Move to the value with given index, or to the ghost position if the index is out of bounds.
+    pub fn seek_index(&mut self, index: usize) {
+        self.index = index.min(self.set.len());
+    }
+
+    /// Move to the value equal to the key, or the smallest greater value if the key is not in the set.
+    /// Return true if the key was found.
+    pub fn seek_key<KEY>(&mut self, key: &KEY) -> bool
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        match self.set.root.find_index(key, &self.set.comparator) {
+            Ok(index) => { self.index = index; true },
+            Err(index) => { self.index = index; false },
+        }
+    }
+
+    /// Move to the next value. Moves from the last value to the ghost position, and from the ghost position
+    /// to the first value.
+    pub fn move_next(&mut self) {
+        self.index = if self.index < self.set.len() { self.index + 1 } else { 0 };
+        self.index = self.index.min(self.set.len());
+    }
+
+    /// Move to the previous value. Moves from the first value to the ghost position, and from the ghost position
+    /// to the last value.
+    pub fn move_prev(&mut self) {
+        let len = self.set.len();
+        self.index = match self.index {
+            0 => len,
+            index if index >= len => len.saturating_sub(1),
+            index => index - 1,
+        };
+    }
+
+    /// Remove the current value from the set and return it. The cursor moves to the next value,
+    /// or to the ghost position if the last value was removed. Does nothing at the ghost position.
+    pub fn remove_current(&mut self) -> Option<T> {
+        self.set.take_by_index(self.index)
+    }
+
+    /// Insert a value into the set and move the cursor to it.
+    /// If an equal value was already in the set, the cursor moves to it, the old value stays in place and false is
+    /// returned.
+    ///
+    /// # Examples:
+    /// ```
+    ///   #  use hitree::hiset::HiSet;
+    ///     let mut s = HiSet::<i32>::from([10,20,30].into_iter());
+    ///     let mut c = s.cursor_mut_at(0);
+    ///     assert!(c.insert(25));
+    ///     assert_eq!(c.index(), Some(2));
+    ///     assert_eq!(c.peek_next(), Some(&30));
+    ///     assert!(!c.insert(10));
+    ///     assert_eq!(c.index(), Some(0));
+    /// ```
+    pub fn insert(&mut self, value: impl Into<T>) -> bool {
+        match self.set.root.insert(Node::new(value), &self.set.comparator, false) {
+            Ok(index) => {
+                self.index = index;
+                true
+            },
+            Err(index) => {
+                self.index = index;
+                false
+            }
         }
     }
-}
-
-
-
-
-impl <'set,T> IntoIterator for &'set HiSet<T>
-    where T: Ord
-{
-    type Item = &'set T;
-    type IntoIter = HiSetIterator<'set,T>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+    /// Return a read-only cursor at the same position, borrowing this cursor.
+    pub fn as_cursor(&self) -> Cursor<'_,T,A,C> {
+        self.set.cursor_at(self.index)
     }
 }
 
-
-pub struct HiSetIteratorMut<'set,T>
-    where T: Ord
+impl <'set,T,C> CursorMut<'set,T,(),C>
+    where C: Comparator<T>
 {
-    set:    &'set mut HiSet<T>,
-    start:  usize,
-    end:    usize,
+    /// Borrow mutably the current value, or return `None` at the ghost position.
+    /// WARNING: You must never change the borrowed value in a way that would affect its ordering according to
+    /// its Ord trait implementation!
+    pub fn current_mut(&mut self) -> Option<&mut T> {
+        self.set.get_by_index_mut(self.index)
+    }
 }
 
-impl <'set,T> Iterator for HiSetIteratorMut<'set,T>
-    where T: Ord,
-{
-    type Item = &'set mut T;
 
-    fn next<'iter>(&mut self) -> Option<Self::Item> {
-        if self.start >= self.end {
-            None
-        } else {
-            let index_to_return = self.start;
-            self.start += 1;
-            unsafe { std::mem::transmute(self.set.get_by_index_mut(index_to_return)) }
+#[test]
+fn test_hiset_split_append() {
+        fn height<T: Ord>(r: &Ref<T>) -> isize {
+            match r.node() {
+                None => 0,
+                Some(node) => {
+                    assert_eq!(r.count, node.count());
+                    1 + height(&node.left).max(height(&node.right))
+                },
+            }
         }
-    }
+        for len in 0..70 {
+            for index in 0..=len {
+                let mut low = HiSet::<i32>::from_sorted_iter(0..len).unwrap();
+                let mut high = low.split_off_index(index as usize);
+                assert!(low.iter().copied().eq(0..index));
+                assert!(high.iter().copied().eq(index..len));
+                assert!(height(&low.root) <= 2 * tree_height(low.len()));
+                assert!(height(&high.root) <= 2 * tree_height(high.len()));
+                low.append(&mut high);
+                assert!(high.is_empty());
+                assert!(low.iter().copied().eq(0..len));
+                assert!(height(&low.root) <= 2 * tree_height(low.len()));
+            }
+        }
+        // join a small tree to a big one from both sides
+        let mut big = HiSet::<i32>::from_sorted_iter(100..1100).unwrap();
+        for i in (0..100).rev() {
+            let mut small = HiSet::<i32>::new();
+            small.insert(i);
+            small.append(&mut big);
+            big = small;
+            assert!(height(&big.root) <= 2 * tree_height(big.len()));
+        }
+        let mut tail = big.split_off(&1000);
+        assert_eq!((big.len(), tail.len()), (1000, 100));
+        tail.append(&mut HiSet::from((950..1050).step_by(2)));
+        assert!(tail.iter().copied().eq((950..1000).step_by(2).chain(1000..1100)));
 }
 
-impl <'set,T> DoubleEndedIterator for HiSetIteratorMut<'set,T>
-    where T: Ord
-{
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if self.start >= self.end {
-            None
-        } else {
-            self.end -= 1;
-            unsafe { std::mem::transmute(self.set.get_by_index_mut(self.end)) }
+#[test]
+fn test_hiset_retain() {
+        fn height<T: Ord>(r: &Ref<T>) -> isize {
+            r.node().map_or(0, |node| 1 + height(&node.left).max(height(&node.right)) )
         }
-    }
+        let mut s = HiSet::<u32>::from_sorted_iter(0..10000).unwrap();
+        s.retain(|value| value % 10 >= 3);
+        assert_eq!(s.len(), 7000);
+        assert_eq!(height(&s.root), tree_height(7000));
+        let mut visited = Vec::new();
+        s.retain_by_index(|index, value| { visited.push(index); value % 10 != 9 });
+        assert!(visited.into_iter().eq(0..7000));
+        let extracted = s.extract_if(|value| value % 10 == 3);
+        assert_eq!(extracted.count(), 1000);
+        assert!(s.iter().all(|value| (4..9).contains(&(value % 10))));
+        assert_eq!(s.len(), 5000);
+        assert_eq!(s.extract_if(|_| false).next(), None);
+        // values are only removed as far as the iterator is advanced
+        let mut extracted = s.extract_if(|value| value % 10 == 4);
+        assert_eq!((extracted.next(), extracted.next()), (Some(4), Some(14)));
+        drop(extracted);
+        assert_eq!(s.len(), 4998);
+        assert_eq!((s.index_of(&15), s.index_of(&24)), (Some(4), Some(8)));
+        assert_eq!(height(&s.root), tree_height(4998));
+        // a panicking closure leaves the kept values and the ones not visited yet in a valid tree
+        let mut s = HiSet::<u32>::from_sorted_iter(0..100).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            s.retain(|value| if *value == 50 { panic!("retain") } else { value % 2 == 0 });
+        }));
+        assert!(result.is_err());
+        assert!(s.iter().copied().eq((0..50).step_by(2).chain(50..100)));
+        assert_eq!(s.index_of(&75), Some(50));
+        let mut s = HiSet::<u32>::from_sorted_iter(0..100).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            s.extract_if(|value| if *value == 50 { panic!("extract_if") } else { value % 2 == 0 }).count()
+        }));
+        assert!(result.is_err());
+        assert!(s.iter().copied().eq((1..50).step_by(2).chain(50..100)));
 }
 
-impl <'set,T> IntoIterator for &'set mut HiSet<T>
-    where T: Ord
-{
-    type Item = &'set mut T;
-    type IntoIter = HiSetIteratorMut<'set,T>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter_mut()
-    }
+#[test]
+fn test_hiset_remove_range() {
+        for len in 0..40 {
+            for start in 0..=len {
+                for end in start..=len + 1 {
+                    let mut s = HiSet::<i32>::from_sorted_iter(0..len).unwrap();
+                    let removed = s.remove_range_by_index(start as usize..end as usize);
+                    assert!(removed.iter().copied().eq(start..end.min(len)));
+                    assert!(s.iter().copied().eq((0..start).chain(end..len)));
+                }
+            }
+        }
+        let mut s = HiSet::<i32>::from((0..100).map(|i| i * 10));
+        assert!(s.drain_range(55..=100).eq([60,70,80,90,100].into_iter()));
+        assert_eq!(s.remove_range(..).len(), 95);
+        assert!(s.is_empty());
 }
 
-impl <T> HiSet<T>
-    where T: Ord
-{
-    /// Get iterator over `&mut T`
-    ///
-    /// # Examples:
-    ///
-    /// ```
-    ///  # use hitree::hiset::HiSet;
-    /// let mut s = HiSet::<String>::new();
-    /// s.insert("This");
-    /// s.insert("is");
-    /// s.insert("a");
-    /// s.insert("test!");
-    ///
-    /// let mut i = s.iter_mut();
-    ///
-    /// assert_eq!(i.next(), Some(&mut "This".to_string()));
-    /// assert_eq!(i.next(), Some(&mut "a".to_string()));
-    /// assert_eq!(i.next(), Some(&mut "is".to_string()));
-    /// assert_eq!(i.next(), Some(&mut "test!".to_string()));
-    /// assert_eq!(i.next(), None);
-    ///
-    /// ```
-    pub fn iter_mut(&mut self) -> HiSetIteratorMut<'_,T> {
-        let end = self.root.count;
-        HiSetIteratorMut { set: self, start: 0, end }
-    }
-
+#[test]
+fn test_hiset_cursor() {
+        for len in 0..40 {
+            let s = HiSet::<i32>::from_sorted_iter(0..len).unwrap();
+            let mut c = s.cursor_at(len as usize);
+            assert_eq!(c.current(), None);
+            for round in 0..2 {
+                // full cycle forward, through the ghost position
+                for value in 0..len {
+                    c.move_next();
+                    assert_eq!((c.index(), c.current()), (Some(value as usize), Some(&value)), "round {}", round);
+                }
+                c.move_next();
+                assert_eq!((c.index(), c.current()), (None, None));
+            }
+            for value in (0..len).rev() {
+                c.move_prev();
+                assert_eq!((c.index(), c.current()), (Some(value as usize), Some(&value)));
+                assert_eq!(c.peek_next().copied(), if value + 1 < len { Some(value + 1) } else { None });
+            }
+            c.move_prev();
+            assert_eq!((c.index(), c.current()), (None, None));
+        }
 }
 
+#[test]
+fn test_hiset_cursor_mut() {
+        let mut s = HiSet::<i32>::from_sorted_iter((0..20).map(|i| i * 2)).unwrap();
+        let mut c = s.cursor_mut_at_key(&7);
+        assert_eq!(c.current(), Some(&8));
+        assert!(c.insert(7));
+        assert_eq!((c.index(), c.peek_prev(), c.peek_next()), (Some(4), Some(&6), Some(&8)));
+        assert_eq!(c.remove_current(), Some(7));
+        assert_eq!(c.current(), Some(&8));
+        c.seek_index(19);
+        assert_eq!(c.remove_current(), Some(38));
+        assert_eq!(c.current(), None);
+        c.move_prev();
+        assert_eq!(c.current(), Some(&36));
+        assert_eq!(c.as_cursor().peek_prev(), Some(&34));
+        assert_eq!(s.len(), 19);
 
-impl <T,I,X,O> From<I> for HiSet<T>
-    where T: Ord,
-          I: Iterator<Item=X>,
-          O: Into<T>,
-          X: ToOwned<Owned=O>
-{
-    /// Construct `HiSet<T>` from an `Iterator` of values that can be made into owned instances of `T`
-    ///
-    /// # Examples:
-    ///
-    /// ```
-    /// # use hitree::hiset::HiSet;
-    /// let s = HiSet::<String>::from( ["This","is","a","test!"].into_iter() );
-    ///
-    /// assert!(s.iter().eq(["This","a","is","test!"].iter()));
-    /// ```
-    fn from(mut iterator: I) -> Self {
-        let mut s = HiSet::<T>::new();
-        while let Some(value) = iterator.next() {
-            s.insert(value.to_owned());
-        }
-        s
-    }
+        // the set stays intact when a cursor is forgotten
+        let mut s = HiSet::<i32>::from_sorted_iter(0..100).unwrap();
+        let mut c = s.cursor_mut_at(50);
+        c.move_next();
+        assert_eq!(c.remove_current(), Some(51));
+        assert!(c.insert(-1));
+        c.move_next();
+        #[allow(clippy::forget_non_drop)]       // keeps testing the set, should the cursor get a Drop
+        std::mem::forget(c);
+        assert_eq!(s.len(), 100);
+        assert!(s.iter().copied().eq([-1].into_iter().chain(0..51).chain(52..100)));
+        assert_eq!(s.index_of(&99), Some(99));
 }
 
 
 //---------------- Ref -------------------------------------------------------
 
-impl <T> Ref<T>
-    where T: Ord
+impl <T,A> Ref<T,A>
+    where A: Augment<T>
 {
 
-    pub fn to(node: Box<Node<T>>) -> Ref<T> {
+    pub fn to(mut node: Box<Node<T,A>>) -> Ref<T,A> {
+        node.update_aggregate();
         let count = 1 + node.left.count + node.right.count;
         Ref { count, node: Some(node) }
     }
 
 
-    fn node(&self) -> Option<&Node<T>> {
+    /// Build perfectly balanced subtree from values already in strictly ascending order, without checking it.
+    fn from_sorted_vec(values: Vec<T>) -> Ref<T,A> {
+        let count = values.len();
+        Ref::from_sorted(&mut values.into_iter(), count)
+    }
+
+    /// Build perfectly balanced subtree of `count` values taken in order from the iterator.
+    fn from_sorted(values: &mut impl Iterator<Item=T>, count: usize) -> Ref<T,A> {
+        if count == 0 {
+            Ref::default()
+        } else {
+            let left_count = (count - 1) / 2;
+            let left = Ref::from_sorted(values, left_count);
+            let value = values.next().unwrap();    // caller guarantees there is enough values
+            let right = Ref::from_sorted(values, count - 1 - left_count);
+            let mut node = Node::new(value);
+            node.left = left;
+            node.right = right;
+            Ref::to(node)
+        }
+    }
+
+
+    fn node(&self) -> Option<&Node<T,A>> {
         self.node.as_deref()
     }
 
-    fn node_mut(&mut self) -> Option<&mut Node<T>> {
+    fn node_mut(&mut self) -> Option<&mut Node<T,A>> {
         self.node.as_deref_mut()
     }
 
 
-    fn take(&mut self) -> Ref<T> {
+    fn take(&mut self) -> Ref<T,A> {
         std::mem::take(&mut *self)
     }
 
-    fn take_left_subtree(&mut self) -> Ref<T> {
+    fn take_left_subtree(&mut self) -> Ref<T,A> {
         match self.node_mut() {
             None => Ref::default(),
             Some(node) => {
                 let left = node.left.take();
-                self.count -= left.count;
+                self.update();
                 left
             },
         }
     }
 
-    fn take_right_subtree(&mut self) -> Ref<T> {
+    fn take_right_subtree(&mut self) -> Ref<T,A> {
         match self.node_mut() {
             None => Ref::default(),
             Some(node) => {
                 let right = node.right.take();
-                self.count -= right.count;
+                self.update();
                 right
             },
         }
@@ -842,16 +3743,16 @@ impl <T> Ref<T>
     }
 
 
-    fn set_left(&mut self, subtree: Ref<T>) {
+    fn set_left(&mut self, subtree: Ref<T,A>) {
         let node = self.node_mut().unwrap();
         node.left = subtree;
-        self.count = node.count();
+        self.update();
     }
 
-    fn set_right(&mut self, subtree: Ref<T>) {
+    fn set_right(&mut self, subtree: Ref<T,A>) {
         let node = self.node_mut().unwrap();
         node.right = subtree;
-        self.count = node.count();
+        self.update();
     }
 
     /*
@@ -896,40 +3797,298 @@ impl <T> Ref<T>
 
 
 
+    fn leftmost_node(&self) -> Option<&Node<T,A>> {
+        let mut node = self.node()?;
+        while let Some(left) = node.left.node() {
+            node = left;
+        }
+        Some(node)
+    }
+
+    fn rightmost_node(&self) -> Option<&Node<T,A>> {
+        let mut node = self.node()?;
+        while let Some(right) = node.right.node() {
+            node = right;
+        }
+        Some(node)
+    }
+
+
+    /// Join two subtrees and a node between them into a balanced subtree.
+    /// All values in `left` must be smaller than the value of `middle`, which must be smaller than all values in `right`.
+    /// Descends along the edge of the taller subtree until heights match, so it takes O(difference in heights).
+    fn join(mut left: Ref<T,A>, mut middle: Box<Node<T,A>>, mut right: Ref<T,A>) -> Ref<T,A> {
+        let balance = tree_height(right.count) - tree_height(left.count);
+        if balance > 1 {
+            // right is much taller, join into its left edge
+            let node = right.node_mut().unwrap();
+            node.left = Ref::join(left, middle, node.left.take());
+            right.rebalance();
+            right
+        } else if balance < -1 {
+            // left is much taller, join into its right edge
+            let node = left.node_mut().unwrap();
+            node.right = Ref::join(node.right.take(), middle, right);
+            left.rebalance();
+            left
+        } else {
+            middle.left = left;
+            middle.right = right;
+            Ref::to(middle)
+        }
+    }
+
+    /// Join two subtrees into a balanced subtree. All values in `left` must be smaller than all values in `right`.
+    fn join2(left: Ref<T,A>, mut right: Ref<T,A>) -> Ref<T,A> {
+        match right.take_leftmost_node() {
+            None => left,
+            Some(middle) => Ref::join(left, middle, right),
+        }
+    }
+
+    /// Split the subtree into values with index `< index` and the rest, both balanced.
+    fn split_at_index(self, index: usize) -> (Ref<T,A>, Ref<T,A>) {
+        match self.node {
+            None => (Ref::default(), Ref::default()),
+            Some(mut node) => {
+                let left = node.left.take();
+                let right = node.right.take();
+                let index_of_this_node = left.count;
+                if index <= index_of_this_node {
+                    let (left_low, left_high) = left.split_at_index(index);
+                    (left_low, Ref::join(left_high, node, right))
+                } else {
+                    let (right_low, right_high) = right.split_at_index(index - index_of_this_node - 1);
+                    (Ref::join(left, node, right_low), right_high)
+                }
+            }
+        }
+    }
+
+
+    /// Cut values with index in `start..end` out of the subtree and return them as a separate subtree.
+    fn take_range(&mut self, start: usize, end: usize) -> Ref<T,A> {
+        let (low, rest) = self.take().split_at_index(start);
+        let (middle, high) = rest.split_at_index(end.max(start) - start);
+        *self = Ref::join2(low, high);
+        middle
+    }
+
+
+    /// Combine aggregates of values with index in `start..end`, using whole subtree aggregates where possible.
+    fn aggregate_range(&self, start: usize, end: usize) -> Option<A::Value> {
+        let node = self.node()?;
+        if start == 0 && end >= self.count {
+            return Some(node.aggregate.clone())
+        }
+        let index = node.left.count;
+        let mut aggregate = None;
+        if start < index {
+            aggregate = node.left.aggregate_range(start, end.min(index));
+        }
+        if start <= index && index < end {
+            let leaf = A::leaf(&node.value);
+            aggregate = Some(match aggregate { None => leaf, Some(left) => A::combine(left, leaf) });
+        }
+        if end > index + 1 {
+            if let Some(right) = node.right.aggregate_range(start.max(index + 1) - index - 1, end - index - 1) {
+                aggregate = Some(match aggregate { None => right, Some(left) => A::combine(left, right) });
+            }
+        }
+        aggregate
+    }
+
+
+    /// Append parts of the subtree with index in `start..end` to `pending` in order,
+    /// as whole subtrees where possible and single values along the paths to `start` and `end`.
+    fn pending_range<'set>(&'set self, start: usize, end: usize, pending: &mut VecDeque<Pending<'set,T,A>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(Pending::Subtree(self));
+            }
+        } else if let Some(node) = self.node() {
+            let index = node.left.count;
+            if start < index {
+                node.left.pending_range(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(Pending::Value(&node.value));
+            }
+            if end > index + 1 {
+                node.right.pending_range(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+
+
+
+    /// Owned version of `pending_range`. Parts of the subtree outside of `start..end` are dropped.
+    fn into_pending_range(self, start: usize, end: usize, pending: &mut VecDeque<PendingOwned<T,A>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(PendingOwned::Subtree(self));
+            }
+        } else if let Some(node) = self.node {
+            let Node { value, left, right, .. } = *node;
+            let index = left.count;
+            if start < index {
+                left.into_pending_range(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(PendingOwned::Value(value));
+            }
+            if end > index + 1 {
+                right.into_pending_range(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+
+
+    /// Find index of node by key reference.
+    /// Returns `Ok(index)` if the key was found, or `Err(index)` with the index a node with this key would have
+    /// if it was inserted.
+    fn find_index<KEY,C>(&self, key: &KEY, comparator: &C) -> Result<usize,usize>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let mut current_node = self.node();
+        let mut current_index_shift = 0;
+        loop {
+            match current_node {
+                None => return Err(current_index_shift),
+                Some(node) => {
+                    match comparator.compare(node.value.borrow(), key) {
+                        Ordering::Greater => {
+                            // index must be in the left subtree
+                            current_node = node.left.node();
+                        },
+                        Ordering::Equal => {
+                            // found it, its this node
+                            return Ok(current_index_shift + node.left.count)
+                        },
+                        Ordering::Less => {
+                            // index must be in the right subtree
+                            current_node = node.right.node();
+                            current_index_shift += 1 + node.left.count;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// Find index of the first node not less than the key, or the first node greater than the key if `upper` is set.
+    /// Unlike `find_index`, this works for subtrees holding several values equal to the key too.
+    fn bound_index<KEY,C>(&self, key: &KEY, comparator: &C, upper: bool) -> usize
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let mut current_node = self.node();
+        let mut current_index_shift = 0;
+        while let Some(node) = current_node {
+            match (comparator.compare(node.value.borrow(), key), upper) {
+                (Ordering::Less, _) | (Ordering::Equal, true) => {
+                    // bound must be in the right subtree
+                    current_index_shift += 1 + node.left.count;
+                    current_node = node.right.node();
+                },
+                (Ordering::Greater, _) | (Ordering::Equal, false) => {
+                    // bound is this node or in the left subtree
+                    current_node = node.left.node();
+                }
+            }
+        }
+        current_index_shift
+    }
+
+
+    /// Find the closest node below (or above if `below` is false) the key, or the node equal to the key if
+    /// `inclusive` is set. Return its index along with the node.
+    fn find_closest<KEY,C>(&self, key: &KEY, comparator: &C, below: bool, inclusive: bool) -> Option<(usize,&Node<T,A>)>
+        where KEY: ?Sized, T: Borrow<KEY>, C: Comparator<KEY>
+    {
+        let mut current_node = self.node();
+        let mut current_index_shift = 0;
+        let mut closest = None;
+        loop {
+            match current_node {
+                None => return closest,
+                Some(node) => {
+                    let index = current_index_shift + node.left.count;
+                    match (comparator.compare(node.value.borrow(), key), below) {
+                        (Ordering::Equal, _) if inclusive => {
+                            // exact match is the closest one
+                            return Some((index, node))
+                        },
+                        (Ordering::Less, true) => {
+                            // candidate, but there may be a closer one in the right subtree
+                            closest = Some((index, node));
+                            current_node = node.right.node();
+                            current_index_shift = index + 1;
+                        },
+                        (Ordering::Greater, false) => {
+                            // candidate, but there may be a closer one in the left subtree
+                            closest = Some((index, node));
+                            current_node = node.left.node();
+                        },
+                        (Ordering::Less, false) | (Ordering::Equal, false) => {
+                            current_node = node.right.node();
+                            current_index_shift = index + 1;
+                        },
+                        (Ordering::Greater, true) | (Ordering::Equal, true) => {
+                            current_node = node.left.node();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+
     /// insert is recursive as it needs to balance the tree on the way back up
-    fn insert(&mut self, new_node: Box<Node<T>>) -> bool {
+    /// With `duplicates` set, a value equal to values already in the subtree is inserted after them.
+    /// Return index of the inserted value in the subtree, or index of the equal value already there as an error.
+    fn insert<C>(&mut self, new_node: Box<Node<T,A>>, comparator: &C, duplicates: bool) -> Result<usize,usize>
+        where C: Comparator<T>
+    {
         match self.node_mut() {
             None => {   // there are no nodes in subtree rooted at this Ref.
                 *self = Ref::to(new_node);
-                true    // we have inserted a value, return true
+                Ok(0)   // we have inserted a value, it is the only one here
             },
             Some(node) => {     // There is at least one node
-                match Ord::cmp(&node.value,&new_node.value) {
+                let order = match comparator.compare(&node.value, &new_node.value) {
+                    Ordering::Equal if duplicates => Ordering::Less,
+                    order => order
+                };
+                match order {
                     Ordering::Equal => {
-                        false   // already in there, return false
+                        Err(node.left.count)    // already in there, return its index
                     },
                     Ordering::Less => { // insert into right subtree
-                        if node.right.insert(new_node) {
-                            self.count += 1;    // increase number of entries for subtree
-                            if self.balance() > 1 { // too right heavy
-                                // difference in height has become greater than 1, rotate subtree left
-                                self.rotate_left();
-                            }
-                            true
-                        } else {
-                            false
+                        let right_start = node.left.count + 1;     // index of the first value of the right subtree
+                        match node.right.insert(new_node, comparator, duplicates) {
+                            Ok(index) => {
+                                self.update();      // increase number of entries for subtree
+                                if self.balance() > 1 { // too right heavy
+                                    // difference in height has become greater than 1, rotate subtree left
+                                    self.rotate_left();
+                                }
+                                Ok(right_start + index)
+                            },
+                            Err(index) => Err(right_start + index),
                         }
                     },
                     Ordering::Greater => {
-                        if node.left.insert(new_node) {
-                            self.count += 1;    // increase number of entries for subtree
-                            if self.balance() < -1 {    // too left heavy
-                                // difference in height has become greater than 1, rotate subtree left
-                                self.rotate_right();
-                            }
-                            true
-                        } else {
-                            false
+                        match node.left.insert(new_node, comparator, duplicates) {
+                            Ok(index) => {
+                                self.update();      // increase number of entries for subtree
+                                if self.balance() < -1 {    // too left heavy
+                                    // difference in height has become greater than 1, rotate subtree left
+                                    self.rotate_right();
+                                }
+                                Ok(index)
+                            },
+                            Err(index) => Err(index),
                         }
                     }
                 }
@@ -938,7 +4097,7 @@ impl <T> Ref<T>
     }
 
     /// Remove leftmost node from the subtree.
-    fn take_leftmost_node(&mut self) -> Option<Box<Node<T>>> {
+    fn take_leftmost_node(&mut self) -> Option<Box<Node<T,A>>> {
         match self.node_mut() {
             None => None,   // no node here, tell caller to remove his node
             Some(node) => {
@@ -950,7 +4109,7 @@ impl <T> Ref<T>
                         Some(removed_node)
                     },
                     Some(removed_node) => {
-                        self.count -= 1;    // one node has been removed
+                        self.update();      // one node has been removed
                         if self.balance() > 1 {     // if we are too right leaning now, restore balance
                             self.rotate_left();
                         }
@@ -962,20 +4121,20 @@ impl <T> Ref<T>
     }
 
     /// Remove rightmost node from the subtree.
-    fn take_rightmost_node(&mut self) -> Option<Box<Node<T>>> {
+    fn take_rightmost_node(&mut self) -> Option<Box<Node<T,A>>> {
         match self.node_mut() {
             None => None,   // no node here, tell caller to remove his node
             Some(node) => {
-                match node.right.take_leftmost_node() {
+                match node.right.take_rightmost_node() {
                     None => {
-                        // there is no left node, we are the node to remove!
+                        // there is no right node, we are the node to remove!
                         let mut removed_node = self.node.take().unwrap();
                         *self = removed_node.left.take();
                         Some(removed_node)
                     },
                     Some(removed_node) => {
-                        self.count -= 1;    // one node has been removed
-                        if self.balance() < -1 {     // if we are too right leaning now, restore balance
+                        self.update();      // one node has been removed
+                        if self.balance() < -1 {     // if we are too left leaning now, restore balance
                             self.rotate_right();
                         }
                         Some(removed_node)
@@ -985,12 +4144,13 @@ impl <T> Ref<T>
         }
     }
 
-    fn take_node_by_key<KEY>(&mut self, key: &KEY) -> Option<Box<Node<T>>>
-        where KEY: ?Sized + Ord,
-            T: Borrow<KEY>
+    fn take_node_by_key<KEY,C>(&mut self, key: &KEY, comparator: &C) -> Option<Box<Node<T,A>>>
+        where KEY: ?Sized,
+            T: Borrow<KEY>,
+            C: Comparator<KEY>
     {
         let res = if let Some(node) = self.node_mut() {
-            match Ord::cmp(node.value.borrow(), key) {
+            match comparator.compare(node.value.borrow(), key) {
                 Ordering::Equal => {    // this is the node to remove
                     match (node.left.is_empty(), node.right.is_empty()) {
                         (true, true) => {    // leaf node, can be removed directly without consequences
@@ -1026,21 +4186,10 @@ impl <T> Ref<T>
                     }
                 },
                 Ordering::Less => {     // node must be in the right subtree
-                    let removed_node_maybe = node.right.take_node_by_key(key);
-                    match removed_node_maybe {
-                        None => None,   // not found
-                        Some(removed_node) => {
-                            Some(removed_node)
-                        }
-                    }
+                    node.right.take_node_by_key(key, comparator)
                 },
                 Ordering::Greater => {  // node must be in the left subtree
-                    match node.left.take_node_by_key(key) {
-                        None => None,
-                        Some(removed_node) => {
-                            Some(removed_node)
-                        }
-                    }
+                    node.left.take_node_by_key(key, comparator)
                 }
             }
         } else {
@@ -1052,7 +4201,7 @@ impl <T> Ref<T>
         res
     }
 
-    fn take_node_by_index(&mut self, index_to_take: usize) -> Option<Box<Node<T>>> {
+    fn take_node_by_index(&mut self, index_to_take: usize) -> Option<Box<Node<T,A>>> {
         let res = if let Some(node) = self.node_mut() {
             let index_of_this_node = node.left.count;
             match Ord::cmp(&index_of_this_node, &index_to_take) {
@@ -1091,21 +4240,10 @@ impl <T> Ref<T>
                     }
                 },
                 Ordering::Less => {     // node must be in the right subtree
-                    let removed_node_maybe = node.right.take_node_by_index(index_to_take - index_of_this_node - 1);
-                    match removed_node_maybe {
-                        None => None,   // not found
-                        Some(removed_node) => {
-                            Some(removed_node)
-                        }
-                    }
+                    node.right.take_node_by_index(index_to_take - index_of_this_node - 1)
                 },
                 Ordering::Greater => {  // node must be in the left subtree
-                    match node.left.take_node_by_index(index_to_take) {
-                        None => None,
-                        Some(removed_node) => {
-                            Some(removed_node)
-                        }
-                    }
+                    node.left.take_node_by_index(index_to_take)
                 }
             }
         } else {
@@ -1118,41 +4256,55 @@ impl <T> Ref<T>
     }
 
     fn rebalance(&mut self) {
-        if let Some(node) = self.node() {
-            self.count = node.count();
+        self.update();
+        if !self.is_empty() {
             let balance = self.balance();
             if balance < -1 {
                 self.rotate_right();
             } else if balance > 1 {
                 self.rotate_left();
             }
-        } else {
-            self.count = 0;
-        };
+        }
     }
 
-
-    /// Take fist value without bothering to re-balance or maintain node counts. For use within owned iterator.
-    fn consume_next(&mut self) -> Option<T> {
-        // Take node from left subtree if any, or
-        // Take node from yourself, replacing it with the right subtree root node if any
-        match &mut self.node {
-            None => None,   // no nodes left, end of iteration
+    /// Recalculate count and aggregate of the subtree after its node or the node's subtrees changed.
+    fn update(&mut self) {
+        match self.node.as_deref_mut() {
+            None => self.count = 0,
             Some(node) => {
-                if let Some(from_left) = node.left.consume_next() {
-                    Some(from_left)
-                } else {
-                    let right_node = node.right.node.take();
-                    let my_node = unsafe { std::mem::replace(&mut self.node, right_node ).unwrap_unchecked() };
-                    Some(my_node.value)
-                }
+                node.update_aggregate();
+                self.count = node.count();
             }
         }
     }
 }
 
-impl <T> Default for Ref<T>
-    where T: Ord
+impl <T> Ref<T>
+{
+    /// Mutable version of `pending_range`. Appended parts are disjoint borrows of the subtree.
+    fn pending_range_mut<'set>(&'set mut self, start: usize, end: usize, pending: &mut VecDeque<PendingMut<'set,T>>) {
+        if start == 0 && end >= self.count {
+            if !self.is_empty() {
+                pending.push_back(PendingMut::Subtree(self));
+            }
+        } else if let Some(node) = self.node_mut() {
+            let Node { value, left, right, .. } = node;
+            let index = left.count;
+            if start < index {
+                left.pending_range_mut(start, end.min(index), pending);
+            }
+            if start <= index && index < end {
+                pending.push_back(PendingMut::Value(value));
+            }
+            if end > index + 1 {
+                right.pending_range_mut(start.max(index + 1) - index - 1, end - index - 1, pending);
+            }
+        }
+    }
+}
+
+impl <T,A> Default for Ref<T,A>
+    where A: Augment<T>
 {
     /// Empty reference
     fn default() -> Self {
@@ -1166,12 +4318,26 @@ impl <T> Default for Ref<T>
 
 
 
-impl <T> Node<T>
-    where T: Ord
+impl <T,A> Node<T,A>
+    where A: Augment<T>
 {
     /// Creates a new Node with given value and empty left & right refs
-    fn new(value: impl Into<T>) -> Box<Node<T>> {
-        Box::new( Node { value: value.into(), left: Ref::default(), right: Ref::default() } )
+    fn new(value: impl Into<T>) -> Box<Node<T,A>> {
+        let value = value.into();
+        let aggregate = A::leaf(&value);
+        Box::new( Node { value, left: Ref::default(), right: Ref::default(), aggregate } )
+    }
+
+    /// Recalculate aggregate of the subtree rooted at this node from the value and aggregates of both subtrees.
+    fn update_aggregate(&mut self) {
+        let mut aggregate = A::leaf(&self.value);
+        if let Some(left) = self.left.node() {
+            aggregate = A::combine(left.aggregate.clone(), aggregate);
+        }
+        if let Some(right) = self.right.node() {
+            aggregate = A::combine(aggregate, right.aggregate.clone());
+        }
+        self.aggregate = aggregate;
     }
 
     /// Calculate number of nodes including this node and any subtrees pointed to by left & right
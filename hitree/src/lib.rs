@@ -4,30 +4,83 @@
 //! node count tracking, allowing easy random access by index of the value within the order implied
 //! by the set value or map key. Tree nodes are stored on heap using [Box](`std::boxed::Box`).
 //!
+//! The crate contains no `unsafe` code. Mutable iterators hand out borrows of disjoint parts of the tree
+//! obtained by splitting nodes into their value and subtrees, so their soundness is checked by the compiler.
+//!
 //! ## Features
 //!
-//! | Feature | [HiSet](hiset::HiSet<T>) | [HiMap](himap::HiMap<T>) |
+//! | Feature | [HiSet](hiset::HiSet<T>) | [HiMap](himap::HiMap<K,V>) |
 //! | ------- | ------- | ------- |
-//! | Zero allocation initialization | [new](`hiset::HiSet<T>::new`) | todo |
-//! | Insert with automatic conversion | [insert](`hiset::HiSet<T>::insert`) | todo |
-//! | Borrow value by index | [get_by_index](`hiset::HiSet<T>::get_by_index<B>`) <br>[get_by_index_mut](`hiset::HiSet<T>::get_by_index_mut<B>`) | todo |
-//! | Borrow value by key | [get](`hiset::HiSet<T>::get<KEY>`) <br>[get_mut](`hiset::HiSet<T>::get_mut<KEY>`) | todo |
-//! | Find index of value by key | [index_of](`hiset::HiSet<T>::index_of<KEY>`) | todo |
-//! | Remove first/last entry | [take_first](`hiset::HiSet<T>::take_first`) <br>[take_last](`hiset::HiSet<T>::take_last`) | todo |
-//! | Remove entry by index | [take_by_index](`hiset::HiSet<T>::take_by_index`) | todo |
-//! | Remove entry by key reference | [take](`hiset::HiSet<T>::take`) | todo |
+//! | Zero allocation initialization | [new](`hiset::HiSet<T>::new`) | [new](`himap::HiMap<K,V>::new`) |
+//! | O(n) construction from sorted values | [from_sorted_iter](`hiset::HiSet<T>::from_sorted_iter`) <br>[from_vec](`hiset::HiSet<T>::from_vec`) | |
+//! | Runtime chosen ordering | [with_comparator](`hiset::HiSet<T,(),C>::with_comparator`) <br>[with_augment_and_comparator](`hiset::HiSet<T,A,C>::with_augment_and_comparator`) <br>[comparator](`hiset::HiSet<T,A,C>::comparator`) with [Comparator](`compare::Comparator`) | |
+//! | Standard traits | `Clone`, `Debug`, `Default`, `PartialEq`, `Eq`, `PartialOrd`, `Ord`, `Hash`, `FromIterator`, `Extend` | `Clone`, `Debug`, `Default`, `PartialEq`, `Eq`, `PartialOrd`, `Ord`, `Hash`, `FromIterator`, `Extend` |
+//! | Insert with automatic conversion | [insert](`hiset::HiSet<T>::insert`) | [insert](`himap::HiMap<K,V>::insert`) |
+//! | Borrow value by index | [get_by_index](`hiset::HiSet<T>::get_by_index<B>`) <br>`set[index]` <br>[get_by_index_mut](`hiset::HiSet<T>::get_by_index_mut<B>`) | [get_by_index](`himap::HiMap<K,V>::get_by_index`) <br>[get_by_index_mut](`himap::HiMap<K,V>::get_by_index_mut`) |
+//! | Borrow value by key | [get](`hiset::HiSet<T>::get<KEY>`) <br>[get_mut](`hiset::HiSet<T>::get_mut<KEY>`) <br>[contains](`hiset::HiSet<T>::contains<KEY>`) | [get](`himap::HiMap<K,V>::get<KEY>`) <br>[get_mut](`himap::HiMap<K,V>::get_mut<KEY>`) <br>[contains_key](`himap::HiMap<K,V>::contains_key<KEY>`) |
+//! | Rank of any key | [rank](`hiset::HiSet<T>::rank<KEY>`) <br>[lower_bound_index](`hiset::HiSet<T>::lower_bound_index<KEY>`) <br>[upper_bound_index](`hiset::HiSet<T>::upper_bound_index<KEY>`) | |
+//! | Aggregates over index or key range in O(log n) | [aggregate_by_index](`hiset::HiSet<T,A>::aggregate_by_index`) <br>[aggregate](`hiset::HiSet<T,A>::aggregate<KEY,R>`) with [Augment](`augment::Augment`) | |
+//! | Weighted rank search in O(log n) | [total_weight](`hiset::HiSet<T,Weight>::total_weight`) <br>[weight_before](`hiset::HiSet<T,Weight>::weight_before`) <br>[index_at_weight](`hiset::HiSet<T,Weight>::index_at_weight`) with [Weight](`augment::Weight`) | |
+//! | Order statistics | [count_range](`hiset::HiSet<T>::count_range<KEY,R>`) <br>[quantile](`hiset::HiSet<T>::quantile`) <br>[quantiles](`hiset::HiSet<T>::quantiles`) <br>[percentile](`hiset::HiSet<T>::percentile`) <br>[median](`hiset::HiSet<T>::median`) | |
+//! | Find nearest entry by key | [floor](`hiset::HiSet<T>::floor<KEY>`) <br>[ceiling](`hiset::HiSet<T>::ceiling<KEY>`) <br>[prev](`hiset::HiSet<T>::prev<KEY>`) <br>[next](`hiset::HiSet<T>::next<KEY>`) | [floor](`himap::HiMap<K,V>::floor<KEY>`) <br>[ceiling](`himap::HiMap<K,V>::ceiling<KEY>`) <br>[prev](`himap::HiMap<K,V>::prev<KEY>`) <br>[next](`himap::HiMap<K,V>::next<KEY>`) |
+//! | In-place entry manipulation with index | | [entry](`himap::HiMap<K,V>::entry`) |
+//! | Find index of value by key | [index_of](`hiset::HiSet<T>::index_of<KEY>`) | [index_of](`himap::HiMap<K,V>::index_of<KEY>`) |
+//! | Iterate in order | [iter](`hiset::HiSet<T>::iter`) <br>[iter_mut](`hiset::HiSet<T>::iter_mut`) <br>[into_iter](`hiset::HiSet<T>::into_iter`) | [iter](`himap::HiMap<K,V>::iter`) <br>[iter_mut](`himap::HiMap<K,V>::iter_mut`) <br>[keys](`himap::HiMap<K,V>::keys`) <br>[values](`himap::HiMap<K,V>::values`) <br>[values_mut](`himap::HiMap<K,V>::values_mut`) <br>[into_iter](`himap::HiMap<K,V>::into_iter`) |
+//! | Iterate over index range | [range_by_index](`hiset::HiSet<T>::range_by_index`) <br>[range_by_index_mut](`hiset::HiSet<T>::range_by_index_mut`) <br>[into_range_by_index](`hiset::HiSet<T>::into_range_by_index`) | [range_by_index](`himap::HiMap<K,V>::range_by_index`) <br>[range_by_index_mut](`himap::HiMap<K,V>::range_by_index_mut`) |
+//! | Iterate with global indexes | [iter_indexed](`hiset::HiSet<T>::iter_indexed`) <br>[range_by_index_indexed](`hiset::HiSet<T>::range_by_index_indexed`) <br>[range_indexed](`hiset::HiSet<T>::range_indexed`) | |
+//! | Iterate over key range | [range](`hiset::HiSet<T>::range`) <br>[index_range](`hiset::HiSet<T>::index_range`) | [range](`himap::HiMap<K,V>::range`) <br>[range_mut](`himap::HiMap<K,V>::range_mut`) <br>[index_range](`himap::HiMap<K,V>::index_range`) |
+//! | Navigate and edit with a cursor | [cursor_at](`hiset::HiSet<T>::cursor_at`) <br>[cursor_at_key](`hiset::HiSet<T>::cursor_at_key<KEY>`) <br>[cursor_mut_at](`hiset::HiSet<T>::cursor_mut_at`) <br>[cursor_mut_at_key](`hiset::HiSet<T>::cursor_mut_at_key<KEY>`) | |
+//! | Set operations | [union](`hiset::HiSet<T>::union`) <br>[intersection](`hiset::HiSet<T>::intersection`) <br>[difference](`hiset::HiSet<T>::difference`) <br>[symmetric_difference](`hiset::HiSet<T>::symmetric_difference`) <br>[union_with](`hiset::HiSet<T>::union_with`) <br>[intersect_with](`hiset::HiSet<T>::intersect_with`) <br>[is_subset](`hiset::HiSet<T>::is_subset`) <br>[is_superset](`hiset::HiSet<T>::is_superset`) <br>[is_disjoint](`hiset::HiSet<T>::is_disjoint`) <br>`&a \| &b`, `&a & &b`, `&a - &b`, `&a ^ &b` | |
+//! | Split and join in O(log n) | [split_off_index](`hiset::HiSet<T>::split_off_index`) <br>[split_off](`hiset::HiSet<T>::split_off<KEY>`) <br>[append](`hiset::HiSet<T>::append`) | |
+//! | Remove first/last entry | [take_first](`hiset::HiSet<T>::take_first`) <br>[take_last](`hiset::HiSet<T>::take_last`) | [take_first](`himap::HiMap<K,V>::take_first`) <br>[take_last](`himap::HiMap<K,V>::take_last`) |
+//! | Remove entry by index | [take_by_index](`hiset::HiSet<T>::take_by_index`) | [take_by_index](`himap::HiMap<K,V>::take_by_index`) |
+//! | Remove index or key range in O(log n) | [remove_range_by_index](`hiset::HiSet<T>::remove_range_by_index`) <br>[remove_range](`hiset::HiSet<T>::remove_range<KEY,R>`) <br>[drain_range](`hiset::HiSet<T>::drain_range<KEY,R>`) | |
+//! | Remove entries matching a predicate in O(n) | [retain](`hiset::HiSet<T>::retain`) <br>[retain_by_index](`hiset::HiSet<T>::retain_by_index`) <br>[extract_if](`hiset::HiSet<T>::extract_if`) | |
+//! | Multiset keeping duplicate values | [HiBag](`hibag::HiBag<T>`) with [count_of](`hibag::HiBag<T>::count_of<KEY>`) <br>[equal_range_index](`hibag::HiBag<T>::equal_range_index<KEY>`) <br>[remove_one](`hibag::HiBag<T>::remove_one<KEY>`) <br>[remove_all](`hibag::HiBag<T>::remove_all<KEY>`) | |
+//! | Remove entry by key reference | [take](`hiset::HiSet<T>::take`) | [remove](`himap::HiMap<K,V>::remove<KEY>`) <br>[remove_entry](`himap::HiMap<K,V>::remove_entry<KEY>`) |
+
+#![forbid(unsafe_code)]
 
 
-/// # Indexable set (incomplete)
+/// # Indexable set
 pub mod hiset;
 
 
-/// # Indexable map (todo)
+/// # Indexable map
 pub mod himap;
 
+
+/// # Indexable multiset
+pub mod hibag;
+
+
+/// # Aggregates kept for subtrees
+pub mod augment;
+
+
+/// # Custom ordering of values
+pub mod compare;
+
 /// estimate maximum height of balanced binary tree containing this many nodes.
 /// Assume all inner nodes are full, only leaf level can be partially filled
 #[inline]
 pub(crate) fn tree_height(count: usize) -> isize {
     (0_usize.leading_zeros()-count.leading_zeros()) as isize
 }
+
+/// Convert range bounds on index into `start..end` pair, with end limited to `len` and start limited to end.
+pub(crate) fn index_bounds(range: impl std::ops::RangeBounds<usize>, len: usize) -> (usize, usize) {
+    use std::ops::Bound::*;
+    let start = match range.start_bound() {
+        Included(index) => *index,
+        Excluded(index) => index.saturating_add(1),
+        Unbounded => 0
+    };
+    let end = match range.end_bound() {
+        Included(index) => index.saturating_add(1),
+        Excluded(index) => *index,
+        Unbounded => len
+    };
+    let end = end.min(len);
+    (start.min(end), end)
+}